@@ -0,0 +1,25 @@
+// build.rs
+// Embeds the git commit for `/version`. Set GIT_HASH to override it, e.g. when
+// building from a source tarball without `.git`.
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let hash = std::env::var("GIT_HASH").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|hash| hash.trim().to_string())
+    });
+
+    println!(
+        "cargo:rustc-env=GIT_HASH={}",
+        hash.as_deref().unwrap_or("unknown")
+    );
+}
@@ -0,0 +1,9 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_refresh_tokens_family_id;
+
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS replaced_by;
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS revoked_at;
+ALTER TABLE refresh_tokens DROP COLUMN IF EXISTS family_id;
+
+ALTER TABLE refresh_tokens
+  ADD CONSTRAINT refresh_tokens_expires_at_check CHECK (expires_at > now()) NOT VALID;
@@ -0,0 +1,13 @@
+-- Add up migration script here
+
+-- The original CHECK rejects any UPDATE on an expired row, which makes
+-- revoking a whole token family impossible once one of its members expired.
+ALTER TABLE refresh_tokens DROP CONSTRAINT IF EXISTS refresh_tokens_expires_at_check;
+
+-- Every login starts a new family; each rotation adds a row to the same family.
+ALTER TABLE refresh_tokens ADD COLUMN family_id UUID NOT NULL DEFAULT gen_random_uuid();
+ALTER TABLE refresh_tokens ALTER COLUMN family_id DROP DEFAULT;
+ALTER TABLE refresh_tokens ADD COLUMN revoked_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE refresh_tokens ADD COLUMN replaced_by UUID REFERENCES refresh_tokens(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_family_id ON refresh_tokens(family_id);
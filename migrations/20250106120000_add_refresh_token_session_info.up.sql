@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE refresh_tokens ADD COLUMN device_label TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN ip_address TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN last_used_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id ON refresh_tokens(user_id);
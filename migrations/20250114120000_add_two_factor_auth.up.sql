@@ -0,0 +1,14 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN totp_secret TEXT;                           -- base32, set by /2fa/setup
+ALTER TABLE users ADD COLUMN totp_enabled_at TIMESTAMP WITH TIME ZONE;   -- set by /2fa/confirm
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;                      -- last accepted time step, blocks code replay
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,                        -- SHA-256 of the normalized code
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id);
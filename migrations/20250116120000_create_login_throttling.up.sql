@@ -0,0 +1,19 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS login_throttles (
+    key TEXT PRIMARY KEY,                           -- "account:<email>" or "ip:<address>"
+    failures INTEGER NOT NULL DEFAULT 0,            -- consecutive failures within the window
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    locked_until TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE IF NOT EXISTS login_failures (
+    id UUID PRIMARY KEY,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL, -- NULL for unknown accounts
+    email TEXT NOT NULL,                            -- as submitted
+    ip_address TEXT,
+    reason TEXT NOT NULL,                           -- invalid_password, unknown_account, invalid_code, locked
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_failures_user_id ON login_failures(user_id);
+CREATE INDEX IF NOT EXISTS idx_login_failures_created_at ON login_failures(created_at);
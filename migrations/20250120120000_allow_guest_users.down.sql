@@ -0,0 +1,7 @@
+-- Add down migration script here
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_credentials_together;
+
+DELETE FROM users WHERE email IS NULL OR password IS NULL;
+
+ALTER TABLE users ALTER COLUMN password SET NOT NULL;
+ALTER TABLE users ALTER COLUMN email SET NOT NULL;
@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- Guests play without credentials until they upgrade to a full account
+ALTER TABLE users ALTER COLUMN email DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN password DROP NOT NULL;
+
+ALTER TABLE users ADD CONSTRAINT users_credentials_together
+    CHECK ((email IS NULL) = (password IS NULL));
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS deleted_users;
+
+ALTER TABLE users DROP COLUMN IF EXISTS deletion_scheduled_at;
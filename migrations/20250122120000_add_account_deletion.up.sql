@@ -0,0 +1,8 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN deletion_scheduled_at TIMESTAMP WITH TIME ZONE; -- hard delete once this passes
+
+-- Usernames of hard-deleted accounts, served as ActivityPub Tombstones
+CREATE TABLE IF NOT EXISTS deleted_users (
+    username TEXT PRIMARY KEY,
+    deleted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
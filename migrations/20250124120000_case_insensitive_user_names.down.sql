@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS users_email_lower_key;
+DROP INDEX IF EXISTS users_username_lower_key;
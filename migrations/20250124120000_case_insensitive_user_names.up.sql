@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- "Alice@localhost" and "alice@localhost" are the same player; same for emails.
+-- Fails if the table already holds names or emails differing only in case.
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_lower_key ON users (lower(username));
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_lower_key ON users (lower(email));
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- One row per simulated battle, so it can be looked at after the fleets have moved on
+CREATE TABLE IF NOT EXISTS battles (
+    id UUID PRIMARY KEY,
+    player_a UUID REFERENCES users(id) ON DELETE SET NULL,  -- the attacker; NULL once the account is gone
+    player_b UUID REFERENCES users(id) ON DELETE SET NULL,
+    seed BIGINT NOT NULL,                                   -- the u64 seed, stored bit for bit
+    winner TEXT NOT NULL,                                   -- "Player A", "Player B" or "Draw"
+    rounds INTEGER NOT NULL,
+    combat_log JSONB NOT NULL,                              -- per round: rolls, damage dealt, units lost
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_battles_player_a ON battles(player_a);
+CREATE INDEX IF NOT EXISTS idx_battles_player_b ON battles(player_b);
@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_battles_player_a_created_at;
+DROP INDEX IF EXISTS idx_battles_player_b_created_at;
+CREATE INDEX IF NOT EXISTS idx_battles_player_a ON battles(player_a);
+CREATE INDEX IF NOT EXISTS idx_battles_player_b ON battles(player_b);
+
+ALTER TABLE battles
+    DROP COLUMN IF EXISTS ruleset_name,
+    DROP COLUMN IF EXISTS ruleset_version,
+    DROP COLUMN IF EXISTS fleets_before,
+    DROP COLUMN IF EXISTS fleets_after;
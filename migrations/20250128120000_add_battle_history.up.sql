@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- Enough about each battle to look it up later: which rules it ran under and what both
+-- fleets looked like before and after. NULL for battles recorded before these columns.
+ALTER TABLE battles
+    ADD COLUMN IF NOT EXISTS ruleset_name TEXT,
+    ADD COLUMN IF NOT EXISTS ruleset_version INTEGER,
+    ADD COLUMN IF NOT EXISTS fleets_before JSONB,   -- {"player_a": {ships, fighters, bombers}, "player_b": {...}}
+    ADD COLUMN IF NOT EXISTS fleets_after JSONB;
+
+-- Battle history is listed per player, newest first
+DROP INDEX IF EXISTS idx_battles_player_a;
+DROP INDEX IF EXISTS idx_battles_player_b;
+CREATE INDEX IF NOT EXISTS idx_battles_player_a_created_at ON battles(player_a, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_battles_player_b_created_at ON battles(player_b, created_at DESC);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE battles
+    DROP COLUMN IF EXISTS simulator_version,
+    DROP COLUMN IF EXISTS max_rounds;
+
+DROP TABLE IF EXISTS rulesets;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Every ruleset the server has run with, by name and version, so old battles can be
+-- replayed with the stats they were fought under after the balance changes
+CREATE TABLE IF NOT EXISTS rulesets (
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    rules JSONB NOT NULL,                                   -- roll and unit stats as loaded
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (name, version)
+);
+
+-- The rest of what a replay needs; NULL for battles recorded before these columns
+ALTER TABLE battles
+    ADD COLUMN IF NOT EXISTS simulator_version INTEGER,
+    ADD COLUMN IF NOT EXISTS max_rounds INTEGER;
@@ -0,0 +1,8 @@
+-- Add down migration script here
+ALTER TABLE battles
+    DROP COLUMN IF EXISTS client_commitment,
+    DROP COLUMN IF EXISTS client_nonce,
+    DROP COLUMN IF EXISTS server_commitment,
+    DROP COLUMN IF EXISTS server_nonce;
+
+DROP TABLE IF EXISTS battle_commitments;
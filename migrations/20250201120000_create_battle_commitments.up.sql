@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- Commit-reveal battle seeds: a player commits to a hashed nonce and gets the hash of a
+-- secret server nonce back; both nonces are revealed when the battle is fought
+CREATE TABLE IF NOT EXISTS battle_commitments (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    client_commitment TEXT NOT NULL,                         -- hex SHA-256 of the player's nonce
+    server_nonce TEXT NOT NULL,                              -- secret until the battle is fought
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_battle_commitments_user_id ON battle_commitments(user_id);
+
+-- Both commitments and nonces, so anyone can check the seed; NULL for older battles
+ALTER TABLE battles
+    ADD COLUMN IF NOT EXISTS client_commitment TEXT,
+    ADD COLUMN IF NOT EXISTS client_nonce TEXT,
+    ADD COLUMN IF NOT EXISTS server_commitment TEXT,
+    ADD COLUMN IF NOT EXISTS server_nonce TEXT;
@@ -0,0 +1,119 @@
+// src/auth/api_key.rs
+// Long-lived keys for bots and dedicated servers, sent as `Authorization: ApiKey <key>`.
+// A key looks like `mpk_<prefix>_<secret>`; the prefix is stored in plain text to find
+// the row, the whole key only as a hash.
+use crate::auth::roles::{Role, parse_roles};
+use crate::auth::token::hash_token;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+const KEY_TAG: &str = "mpk";
+
+/// What an API key may be used for. Interactive logins are not limited by scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Profile, // read the profile and ActivityPub actor
+    Fleets,  // create fleets
+    Battles, // start battles and send battle requests
+    Admin,   // admin endpoints, if the owner is an admin
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Profile => "profile",
+            Scope::Fleets => "fleets",
+            Scope::Battles => "battles",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "profile" => Ok(Scope::Profile),
+            "fleets" => Ok(Scope::Fleets),
+            "battles" => Ok(Scope::Battles),
+            "admin" => Ok(Scope::Admin),
+            other => Err(format!("Unknown scope: {}", other)),
+        }
+    }
+}
+
+/// Parse the `api_keys.scopes` column, skipping scopes this build doesn't know about.
+pub fn parse_scopes(scopes: &[String]) -> Vec<Scope> {
+    scopes.iter().filter_map(|s| s.parse().ok()).collect()
+}
+
+/// A new key as `(prefix, full key)`. Only the prefix and `hash_token(full key)` are stored.
+pub fn generate_key() -> (String, String) {
+    let mut prefix = [0u8; 6];
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut prefix);
+    OsRng.fill_bytes(&mut secret);
+
+    let prefix = hex::encode(prefix);
+    let key = format!("{}_{}_{}", KEY_TAG, prefix, hex::encode(secret));
+    (prefix, key)
+}
+
+fn key_prefix(key: &str) -> Option<&str> {
+    let mut parts = key.splitn(3, '_');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(KEY_TAG), Some(prefix), Some(_secret)) => Some(prefix),
+        _ => None,
+    }
+}
+
+/// The user and permissions behind a valid API key.
+pub struct KeyOwner {
+    pub user_id: Uuid,
+    pub roles: Vec<Role>,
+    pub scopes: Vec<Scope>,
+}
+
+/// Look up an unexpired key and record that it was used.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn authenticate_key(pool: &PgPool, key: &str) -> Result<Option<KeyOwner>, sqlx::Error> {
+    let Some(prefix) = key_prefix(key) else {
+        return Ok(None);
+    };
+
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_keys k
+        SET last_used_at = now()
+        FROM users u
+        WHERE k.prefix = $1
+          AND k.secret_hash = $2
+          AND k.user_id = u.id
+          AND (k.expires_at IS NULL OR k.expires_at > now())
+        RETURNING k.user_id, k.scopes, u.roles as "roles!"
+        "#,
+        prefix,
+        hash_token(key)
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| KeyOwner {
+        user_id: row.user_id,
+        roles: parse_roles(&row.roles),
+        scopes: parse_scopes(&row.scopes),
+    }))
+}
@@ -0,0 +1,263 @@
+// src/auth/extractor.rs
+use crate::auth::api_key::{self, Scope};
+use crate::auth::jwt::{JwtKeys, TokenUse, decode_jwt};
+use crate::auth::roles::Role;
+use crate::config::Features;
+use crate::error::AppError;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, web};
+use futures_util::future::LocalBoxFuture;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The user behind the `Authorization: Bearer <access_token>` or
+/// `Authorization: ApiKey <key>` header.
+///
+/// Add it as a handler argument to make a route require authentication:
+/// requests without a valid token are rejected with 401 before the handler runs.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub id: Uuid,
+    pub roles: Vec<Role>,
+    pub scopes: Option<Vec<Scope>>, // `None` for access tokens, which may do everything
+}
+
+impl AuthenticatedUser {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// Allow API keys only if they were granted `scope`.
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AppError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => Err(AppError::forbidden(format!(
+                "API key lacks scope: {}",
+                scope
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Account management (sessions, 2FA, API keys) needs an interactive login.
+    pub fn require_interactive(&self) -> Result<(), AppError> {
+        match self.scopes {
+            Some(_) => Err(AppError::forbidden(
+                "Not available when authenticated with an API key",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Allow acting on behalf of `user_id` only for that user themselves or an admin.
+    pub fn authorize_for(&self, user_id: Uuid) -> Result<(), AppError> {
+        if self.id == user_id || self.has_role(Role::Admin) {
+            Ok(())
+        } else {
+            Err(AppError::forbidden(
+                "Cannot act on behalf of another player",
+            ))
+        }
+    }
+
+    /// The error for a player named in the request who doesn't exist. Only admins
+    /// learn that; everyone else gets the 403 of `authorize_for`, so the answer
+    /// doesn't tell which usernames are taken.
+    pub fn unknown_player(&self, message: &str) -> AppError {
+        if self.has_role(Role::Admin) {
+            AppError::not_found(message)
+        } else {
+            AppError::forbidden("Cannot act on behalf of another player")
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+/// Authenticate the request, reusing the result if a middleware already did so.
+pub async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, AppError> {
+    if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+        return Ok(user.clone());
+    }
+
+    let header_str = req
+        .headers()
+        .get("Authorization")
+        .ok_or_else(|| AppError::unauthorized("Missing token"))?
+        .to_str()
+        .map_err(|_| AppError::unauthorized("Bad auth header"))?;
+
+    let user = if let Some(token) = header_str.strip_prefix("Bearer ") {
+        user_from_access_token(req, token)?
+    } else if let Some(key) = header_str.strip_prefix("ApiKey ") {
+        user_from_api_key(req, key).await?
+    } else {
+        return Err(AppError::unauthorized("Invalid token format"));
+    };
+
+    // Tag the request's span so everything logged from here on names the caller
+    tracing::Span::current().record("user_id", tracing::field::display(user.id));
+    req.extensions_mut().insert(user.clone());
+    Ok(user)
+}
+
+fn user_from_access_token(req: &HttpRequest, token: &str) -> Result<AuthenticatedUser, AppError> {
+    let keys = req
+        .app_data::<web::Data<JwtKeys>>()
+        .ok_or_else(|| AppError::internal("JWT keys not configured"))?;
+
+    let claims = decode_jwt(token, keys)
+        .ok()
+        .filter(|claims| claims.token_use == TokenUse::Access)
+        .ok_or_else(|| AppError::unauthorized("Invalid token"))?;
+
+    Ok(AuthenticatedUser {
+        id: Uuid::parse_str(&claims.sub).map_err(|_| AppError::unauthorized("Invalid user ID"))?,
+        roles: claims.roles,
+        scopes: None,
+    })
+}
+
+async fn user_from_api_key(req: &HttpRequest, key: &str) -> Result<AuthenticatedUser, AppError> {
+    if req
+        .app_data::<web::Data<Features>>()
+        .is_some_and(|features| !features.api_keys)
+    {
+        return Err(AppError::unauthorized("API keys are disabled"));
+    }
+
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| AppError::internal("Database not configured"))?;
+
+    let owner = api_key::authenticate_key(pool, key)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+
+    Ok(AuthenticatedUser {
+        id: owner.user_id,
+        roles: owner.roles,
+        scopes: Some(owner.scopes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::jwt::{generate_jwt, generate_mfa_pending_jwt};
+    use crate::auth::token::generate_token;
+    use actix_web::ResponseError;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn keys() -> web::Data<JwtKeys> {
+        web::Data::new(JwtKeys::from_secret("test-secret"))
+    }
+
+    fn bearer(token: &str) -> HttpRequest {
+        TestRequest::default()
+            .app_data(keys())
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_http_request()
+    }
+
+    fn player(id: Uuid) -> AuthenticatedUser {
+        AuthenticatedUser {
+            id,
+            roles: vec![Role::Player],
+            scopes: None,
+        }
+    }
+
+    fn status(result: Result<(), AppError>) -> StatusCode {
+        result.map_or_else(|e| e.status_code(), |_| StatusCode::OK)
+    }
+
+    #[actix_web::test]
+    async fn access_token_authenticates() {
+        let id = Uuid::new_v4();
+        let token = generate_jwt(&id.to_string(), &[Role::Admin], &keys());
+
+        let user = authenticate(&bearer(&token)).await.unwrap();
+        assert_eq!(user.id, id);
+        assert_eq!(user.roles, vec![Role::Admin]);
+        assert!(user.scopes.is_none());
+    }
+
+    #[actix_web::test]
+    async fn mfa_pending_token_is_rejected() {
+        let token = generate_mfa_pending_jwt(&Uuid::new_v4().to_string(), &keys());
+
+        let err = authenticate(&bearer(&token)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn refresh_token_is_rejected() {
+        let err = authenticate(&bearer(&generate_token())).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn missing_header_is_rejected() {
+        let req = TestRequest::default().app_data(keys()).to_http_request();
+
+        let err = authenticate(&req).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn api_key_is_not_interactive() {
+        let mut user = player(Uuid::new_v4());
+        assert_eq!(status(user.require_interactive()), StatusCode::OK);
+
+        user.scopes = Some(vec![Scope::Profile, Scope::Fleets]);
+        assert_eq!(status(user.require_interactive()), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn require_scope_checks_api_key_scopes_only() {
+        let mut user = player(Uuid::new_v4());
+        assert_eq!(status(user.require_scope(Scope::Battles)), StatusCode::OK);
+
+        user.scopes = Some(vec![Scope::Fleets]);
+        assert_eq!(status(user.require_scope(Scope::Fleets)), StatusCode::OK);
+        assert_eq!(
+            status(user.require_scope(Scope::Battles)),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn authorize_for_allows_self_and_admins() {
+        let (id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut user = player(id);
+        assert_eq!(status(user.authorize_for(id)), StatusCode::OK);
+        assert_eq!(status(user.authorize_for(other)), StatusCode::FORBIDDEN);
+
+        user.roles.push(Role::Admin);
+        assert_eq!(status(user.authorize_for(other)), StatusCode::OK);
+    }
+
+    #[test]
+    fn unknown_player_is_only_revealed_to_admins() {
+        let mut user = player(Uuid::new_v4());
+        let err = user.unknown_player("User not found");
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            err.to_string(),
+            user.authorize_for(Uuid::new_v4()).unwrap_err().to_string()
+        );
+
+        user.roles.push(Role::Admin);
+        let err = user.unknown_player("User not found");
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,2 +1,8 @@
+pub mod api_key;
+pub mod extractor;
 pub mod jwt;
 pub mod password;
+pub mod roles;
+pub mod throttle;
+pub mod token;
+pub mod totp;
@@ -1,24 +1,146 @@
-// src/auth.rs
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+// src/auth/password.rs
+use crate::config::Config;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use password_hash::SaltString;
 use rand_core::OsRng;
+use std::fmt;
 
-pub fn hash_password(password: &str) -> String {
-    // Let Argon2 handle salt creation
-    let salt_string = SaltString::generate(&mut OsRng);
+#[derive(Debug)]
+pub enum PasswordError {
+    Params(argon2::Error),           // invalid cost parameters in the config
+    Hash(password_hash::Error),      // hashing itself failed
+    Malformed(password_hash::Error), // the stored hash can't be parsed
+    Mismatch,                        // wrong password
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordError::Params(e) => write!(f, "invalid Argon2 parameters: {}", e),
+            PasswordError::Hash(e) => write!(f, "failed to hash password: {}", e),
+            PasswordError::Malformed(e) => write!(f, "malformed password hash: {}", e),
+            PasswordError::Mismatch => f.write_str("password does not match"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+/// Result of a successful verification.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verified {
+    Current,  // hash uses the configured parameters
+    Outdated, // correct password, but the hash should be replaced with `hash_password`
+}
+
+/// Argon2id with the cost parameters from the config. Shared as app data.
+pub struct Hasher {
+    params: Params,
+}
+
+impl Hasher {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Self, PasswordError> {
+        let params = Params::new(memory_kib, iterations, parallelism, None)
+            .map_err(PasswordError::Params)?;
+        Ok(Hasher { params })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, PasswordError> {
+        Hasher::new(
+            config.password_hashing.memory_kib,
+            config.password_hashing.iterations,
+            config.password_hashing.parallelism,
+        )
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
 
-    let argon2 = Argon2::default();
-    let password_hash = argon2
-        .hash_password(password.as_bytes(), &salt_string)
-        .expect("Failed to hash password");
+    pub fn hash_password(&self, password: &str) -> Result<String, PasswordError> {
+        // Let Argon2 handle salt creation
+        let salt_string = SaltString::generate(&mut OsRng);
 
-    password_hash.to_string()
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt_string)
+            .map(|hash| hash.to_string())
+            .map_err(PasswordError::Hash)
+    }
+
+    /// Check `password` against a stored hash. The parameters encoded in the hash
+    /// are used for verification, so hashes from older settings keep working.
+    pub fn verify_password(&self, hash: &str, password: &str) -> Result<Verified, PasswordError> {
+        let parsed_hash = PasswordHash::new(hash).map_err(PasswordError::Malformed)?;
+
+        self.argon2()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .map_err(|e| match e {
+                password_hash::Error::Password => PasswordError::Mismatch,
+                e => PasswordError::Malformed(e),
+            })?;
+
+        if self.is_current(&parsed_hash) {
+            Ok(Verified::Current)
+        } else {
+            Ok(Verified::Outdated)
+        }
+    }
+
+    fn is_current(&self, hash: &PasswordHash<'_>) -> bool {
+        hash.algorithm == Algorithm::Argon2id.ident()
+            && hash.version == Some(Version::V0x13.into())
+            && Params::try_from(hash).is_ok_and(|params| {
+                params.m_cost() == self.params.m_cost()
+                    && params.t_cost() == self.params.t_cost()
+                    && params.p_cost() == self.params.p_cost()
+            })
+    }
 }
 
-pub fn verify_password(hash: &str, password: &str) -> bool {
-    let parsed_hash = PasswordHash::new(hash).unwrap();
-    let argon2 = Argon2::default();
-    argon2
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .is_ok()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Far below the defaults so the tests stay fast; only the comparison matters.
+    fn hasher(memory_kib: u32, iterations: u32) -> Hasher {
+        Hasher::new(memory_kib, iterations, 1).unwrap()
+    }
+
+    #[test]
+    fn hash_with_current_costs_is_current() {
+        let hasher = hasher(64, 2);
+        let hash = hasher.hash_password("correct-horse").unwrap();
+
+        assert_eq!(
+            hasher.verify_password(&hash, "correct-horse").unwrap(),
+            Verified::Current
+        );
+    }
+
+    #[test]
+    fn hash_with_lower_costs_is_outdated() {
+        let old = hasher(32, 1).hash_password("correct-horse").unwrap();
+
+        for current in [hasher(64, 1), hasher(32, 2)] {
+            assert_eq!(
+                current.verify_password(&old, "correct-horse").unwrap(),
+                Verified::Outdated
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_password_and_malformed_hash_are_errors() {
+        let hasher = hasher(64, 2);
+        let hash = hasher.hash_password("correct-horse").unwrap();
+
+        assert!(matches!(
+            hasher.verify_password(&hash, "battery-staple"),
+            Err(PasswordError::Mismatch)
+        ));
+        assert!(matches!(
+            hasher.verify_password("not a hash", "correct-horse"),
+            Err(PasswordError::Malformed(_))
+        ));
+    }
 }
@@ -0,0 +1,231 @@
+// src/auth/throttle.rs
+// Failed-login tracking. Counters live in Postgres so every server instance sees
+// the same lockouts.
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Failures older than this no longer count towards a lockout.
+const FAILURE_WINDOW_SECONDS: f64 = 3600.0;
+/// Longest a key is ever locked; repeated failures keep it locked this long.
+const MAX_LOCKOUT_SECONDS: u64 = 15 * 60;
+/// How long rows stay in the `login_failures` audit table.
+const AUDIT_RETENTION_DAYS: i32 = 90;
+
+/// What a failed attempt is counted against.
+pub enum ThrottleKey {
+    Account(String), // email as submitted
+    Ip(String),
+}
+
+impl ThrottleKey {
+    fn key(&self) -> String {
+        match self {
+            ThrottleKey::Account(email) => format!("account:{}", email.trim().to_lowercase()),
+            ThrottleKey::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+
+    /// Failures allowed before backoff kicks in. An IP is shared by many
+    /// players behind NAT, so it gets more slack than a single account.
+    fn free_attempts(&self) -> u32 {
+        match self {
+            ThrottleKey::Account(_) => 5,
+            ThrottleKey::Ip(_) => 20,
+        }
+    }
+}
+
+/// One row for the `login_failures` audit table.
+pub struct FailedLogin<'a> {
+    pub user_id: Option<Uuid>,
+    pub email: &'a str,
+    pub ip: Option<&'a str>,
+    pub reason: &'static str, // invalid_password, unknown_account, invalid_code, locked, password_reset, malformed_hash
+}
+
+/// Lockout after the `failures`-th consecutive failure: nothing for the first
+/// `free_attempts`, then 1s, 2s, 4s, ... capped at `MAX_LOCKOUT_SECONDS`.
+fn backoff_seconds(failures: u32, free_attempts: u32) -> Option<u64> {
+    let excess = failures.checked_sub(free_attempts).filter(|&n| n > 0)?;
+    Some(
+        2u64.checked_pow(excess - 1)
+            .map_or(MAX_LOCKOUT_SECONDS, |s| s.min(MAX_LOCKOUT_SECONDS)),
+    )
+}
+
+/// Seconds until every key is unlocked, or `None` if none is locked.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn locked_for(pool: &PgPool, keys: &[ThrottleKey]) -> Result<Option<u64>, sqlx::Error> {
+    let keys: Vec<String> = keys.iter().map(ThrottleKey::key).collect();
+
+    let locked_until = sqlx::query_scalar!(
+        r#"
+        SELECT max(locked_until)
+        FROM login_throttles
+        WHERE key = ANY($1) AND locked_until > now()
+        "#,
+        &keys
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(locked_until.map(|until| {
+        let millis = (until - Utc::now()).num_milliseconds().max(1) as u64;
+        millis.div_ceil(1000)
+    }))
+}
+
+/// Count a failure against every key, locking keys past their free attempts,
+/// and write the audit row.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn record_failure(
+    pool: &PgPool,
+    keys: &[ThrottleKey],
+    attempt: FailedLogin<'_>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    for key in keys {
+        let failures = sqlx::query_scalar!(
+            r#"
+            INSERT INTO login_throttles (key, failures, last_failure_at)
+            VALUES ($1, 1, now())
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_throttles.last_failure_at < now() - make_interval(secs => $2)
+                    THEN 1
+                    ELSE login_throttles.failures + 1
+                END,
+                last_failure_at = now()
+            RETURNING failures
+            "#,
+            key.key(),
+            FAILURE_WINDOW_SECONDS
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(seconds) = backoff_seconds(failures as u32, key.free_attempts()) {
+            sqlx::query!(
+                r#"
+                UPDATE login_throttles
+                SET locked_until = now() + make_interval(secs => $2)
+                WHERE key = $1
+                "#,
+                key.key(),
+                seconds as f64
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    record_audit(&mut *tx, &attempt).await?;
+
+    tx.commit().await
+}
+
+/// Audit an attempt rejected because of a lockout. Does not extend the lockout.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn record_locked(pool: &PgPool, attempt: FailedLogin<'_>) -> Result<(), sqlx::Error> {
+    record_audit(pool, &attempt).await
+}
+
+/// Forget the failures of an account after a complete login. IP counters are
+/// left alone so an attacker can't reset them by logging into their own account.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn record_success(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+    forget_account(pool, email).await
+}
+
+/// Drop the failure counter of an account, e.g. when it is deleted.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn forget_account(
+    executor: impl sqlx::PgExecutor<'_>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM login_throttles WHERE key = $1",
+        ThrottleKey::Account(email.to_string()).key()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete counters that no longer count towards a lockout and audit rows past
+/// their retention. Returns the number of rows removed.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn purge_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let throttles = sqlx::query!(
+        r#"
+        DELETE FROM login_throttles
+        WHERE last_failure_at < now() - make_interval(secs => $1)
+          AND (locked_until IS NULL OR locked_until < now())
+        "#,
+        FAILURE_WINDOW_SECONDS
+    )
+    .execute(pool)
+    .await?;
+
+    let failures = sqlx::query!(
+        "DELETE FROM login_failures WHERE created_at < now() - make_interval(days => $1)",
+        AUDIT_RETENTION_DAYS
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(throttles.rows_affected() + failures.rows_affected())
+}
+
+async fn record_audit(
+    executor: impl sqlx::PgExecutor<'_>,
+    attempt: &FailedLogin<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO login_failures (id, user_id, email, ip_address, reason)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        attempt.user_id,
+        attempt.email,
+        attempt.ip,
+        attempt.reason
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_attempts_are_not_locked() {
+        assert_eq!(backoff_seconds(0, 5), None);
+        assert_eq!(backoff_seconds(4, 5), None);
+        assert_eq!(backoff_seconds(5, 5), None);
+        assert_eq!(backoff_seconds(6, 5), Some(1));
+    }
+
+    #[test]
+    fn backoff_doubles_per_failure() {
+        let lockouts: Vec<_> = (6..=10).map(|n| backoff_seconds(n, 5)).collect();
+        assert_eq!(lockouts, [Some(1), Some(2), Some(4), Some(8), Some(16)]);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        // 2^9 = 512s is the last step below the 900s cap
+        assert_eq!(backoff_seconds(15, 5), Some(512));
+        assert_eq!(backoff_seconds(16, 5), Some(MAX_LOCKOUT_SECONDS));
+        // 2^64 overflows; it must still be the cap rather than a panic
+        assert_eq!(backoff_seconds(70, 5), Some(MAX_LOCKOUT_SECONDS));
+        assert_eq!(backoff_seconds(u32::MAX, 20), Some(MAX_LOCKOUT_SECONDS));
+    }
+}
@@ -0,0 +1,16 @@
+// src/auth/token.rs
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+/// Generate an opaque random token (refresh tokens, emailed reset links, ...)
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes) // a hex string like "aabbcc..."
+}
+
+/// Hash a token before storing it, so a leaked table can't be replayed
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
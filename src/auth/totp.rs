@@ -0,0 +1,212 @@
+// src/auth/totp.rs
+// RFC 6238 one-time passwords (SHA-1, 6 digits, 30 second steps), the flavour every
+// authenticator app understands. Nothing here reads the system clock: callers pass
+// the Unix time, so codes can be checked against a fixed clock.
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use super::token::hash_token;
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Accept codes from one step before and after the current one to absorb clock drift.
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+
+/// A new random 160-bit secret, base32 encoded for authenticator apps.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes)
+}
+
+/// The `otpauth://` URI to render as a QR code during enrollment.
+pub fn otpauth_uri(secret: &str, account: &str, issuer: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = url_encode(issuer),
+        account = url_encode(account),
+    )
+}
+
+/// Check `code` at `unix_time`. Returns the matched time step so callers can
+/// refuse to accept the same step twice.
+pub fn verify(secret: &str, code: &str, unix_time: u64) -> Option<u64> {
+    let key = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret)?;
+    let code = code.trim();
+    let current = unix_time / STEP_SECONDS;
+
+    (current.saturating_sub(ALLOWED_DRIFT_STEPS)..=current + ALLOWED_DRIFT_STEPS)
+        .find(|&step| bool::from(hotp(&key, step).as_bytes().ct_eq(code.as_bytes())))
+}
+
+/// Like `verify`, but refuses steps at or before `last_step`, the last one accepted
+/// for this secret, so an observed code can't be replayed.
+pub fn verify_new_step(
+    secret: &str,
+    code: &str,
+    unix_time: u64,
+    last_step: Option<i64>,
+) -> Option<u64> {
+    verify(secret, code, unix_time).filter(|&step| last_step.is_none_or(|last| step as i64 > last))
+}
+
+/// `count` random recovery codes like `3f9a-c07e-11d2`.
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let mut bytes = [0u8; 6];
+            OsRng.fill_bytes(&mut bytes);
+            let hex = hex::encode(bytes);
+            format!("{}-{}-{}", &hex[0..4], &hex[4..8], &hex[8..12])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without dashes, whitespace or case.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Index of the stored hash in `unused_hashes` that `code` matches. Every hash is
+/// compared in constant time; callers mark the match used so it can't be redeemed twice.
+pub fn find_recovery_code(code: &str, unused_hashes: &[String]) -> Option<usize> {
+    let hash = hash_token(&normalize_recovery_code(code));
+    unused_hashes
+        .iter()
+        .position(|stored| bool::from(stored.as_bytes().ct_eq(hash.as_bytes())))
+}
+
+/// RFC 4226 HOTP value for `counter`.
+fn hotp(key: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 4226 / RFC 6238 SHA-1 test key `"12345678901234567890"`.
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn hotp_matches_rfc4226_vectors() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(RFC_KEY, counter as u64), *code, "counter {counter}");
+        }
+    }
+
+    #[test]
+    fn verify_matches_rfc6238_vectors() {
+        // RFC 6238 appendix B, SHA-1, truncated to six digits.
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (unix_time, code) in vectors {
+            assert_eq!(
+                verify(RFC_SECRET, code, unix_time),
+                Some(unix_time / STEP_SECONDS),
+                "T = {unix_time}"
+            );
+        }
+    }
+
+    #[test]
+    fn verify_accepts_one_step_of_drift() {
+        let code = "005924"; // step 41152263
+        let time = 1234567890;
+        assert!(verify(RFC_SECRET, code, time - STEP_SECONDS).is_some());
+        assert!(verify(RFC_SECRET, code, time + STEP_SECONDS).is_some());
+        assert_eq!(verify(RFC_SECRET, code, time - 2 * STEP_SECONDS), None);
+        assert_eq!(verify(RFC_SECRET, code, time + 2 * STEP_SECONDS), None);
+    }
+
+    #[test]
+    fn verify_rejects_wrong_codes_and_secrets() {
+        assert_eq!(verify(RFC_SECRET, "005925", 1234567890), None);
+        assert_eq!(verify(RFC_SECRET, "", 1234567890), None);
+        assert_eq!(verify("not base32!", "005924", 1234567890), None);
+        assert!(verify(RFC_SECRET, " 005924 ", 1234567890).is_some());
+    }
+
+    #[test]
+    fn verify_new_step_rejects_replayed_steps() {
+        let (code, time) = ("005924", 1234567890);
+        let step = verify_new_step(RFC_SECRET, code, time, None).unwrap();
+
+        assert_eq!(
+            verify_new_step(RFC_SECRET, code, time, Some(step as i64)),
+            None
+        );
+        assert_eq!(
+            verify_new_step(RFC_SECRET, code, time, Some(step as i64 + 1)),
+            None
+        );
+        assert_eq!(
+            verify_new_step(RFC_SECRET, code, time, Some(step as i64 - 1)),
+            Some(step)
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_single_use() {
+        let codes = generate_recovery_codes(3);
+        let mut unused: Vec<String> = codes
+            .iter()
+            .map(|code| hash_token(&normalize_recovery_code(code)))
+            .collect();
+
+        // Matching ignores dashes, whitespace and case.
+        let entered = format!(" {} ", codes[1].to_uppercase().replace('-', ""));
+        let index = find_recovery_code(&entered, &unused).unwrap();
+        assert_eq!(index, 1);
+
+        // Redeeming marks the code used; it can't match again.
+        unused.remove(index);
+        assert_eq!(find_recovery_code(&codes[1], &unused), None);
+        assert_eq!(find_recovery_code(&codes[0], &unused), Some(0));
+    }
+}
@@ -0,0 +1,96 @@
+// src/client_ip.rs
+// The address a request came from, for login throttling and the session list.
+// Forwarding headers are only believed when the TCP peer is one of our own proxies
+// (`server.trusted_proxies`); anyone else could put any address in them.
+use crate::config::TrustedProxies;
+use actix_web::{HttpRequest, web};
+use std::net::IpAddr;
+
+/// The client's IP: the TCP peer, or when that is a trusted proxy, the nearest
+/// untrusted address in `X-Forwarded-For`.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded_for = req
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok());
+    let empty = TrustedProxies::default();
+    let trusted = req
+        .app_data::<web::Data<TrustedProxies>>()
+        .map(|data| data.get_ref())
+        .unwrap_or(&empty);
+
+    Some(resolve(peer, forwarded_for, trusted).to_string())
+}
+
+/// Walk `X-Forwarded-For` from the right, where our proxies appended, past every
+/// trusted hop. Garbage in the header stops the walk at the last good address.
+fn resolve(peer: IpAddr, forwarded_for: Option<&str>, trusted: &TrustedProxies) -> IpAddr {
+    let mut client = peer;
+    let Some(forwarded_for) = forwarded_for else {
+        return client;
+    };
+
+    for hop in forwarded_for.rsplit(',') {
+        if !trusted.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies(list: &str) -> TrustedProxies {
+        list.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_for() {
+        let client = resolve(ip("203.0.113.9"), Some("198.51.100.1"), &proxies(""));
+        assert_eq!(client, ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn trusted_proxy_forwards_the_client() {
+        let client = resolve(ip("10.0.0.1"), Some("198.51.100.1"), &proxies("10.0.0.1"));
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn spoofed_entries_left_of_the_client_are_ignored() {
+        // The client sent "1.2.3.4" itself; our proxy appended the real address
+        let client = resolve(
+            ip("10.0.0.1"),
+            Some("1.2.3.4, 198.51.100.1"),
+            &proxies("10.0.0.1"),
+        );
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn chained_proxies_are_skipped() {
+        let client = resolve(
+            ip("10.0.0.1"),
+            Some("198.51.100.1, 10.0.0.2"),
+            &proxies("10.0.0.1,10.0.0.2"),
+        );
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn garbage_stops_at_the_last_trusted_hop() {
+        let client = resolve(ip("10.0.0.1"), Some("not-an-ip"), &proxies("10.0.0.1"));
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+}
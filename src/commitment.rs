@@ -0,0 +1,115 @@
+// src/commitment.rs
+// Commit-reveal battle seeds. The player commits to `commit(nonce)` and gets back the
+// commitment to a random server nonce; neither side can pick the seed once it has seen
+// the other's commitment. The battle reveals both nonces, and anyone can recompute
+// the seed with `battle_seed` and check both commitments against them.
+use crate::auth::token::generate_token;
+use sha2::{Digest, Sha256};
+
+/// Player nonces must be long enough that the server can't guess them from the commitment.
+const MIN_NONCE_LEN: usize = 16;
+const MAX_NONCE_LEN: usize = 128;
+
+/// Hex SHA-256 of a nonce.
+pub fn commit(nonce: &str) -> String {
+    hex::encode(Sha256::digest(nonce.as_bytes()))
+}
+
+/// A fresh secret server nonce: 32 random bytes, hex encoded.
+pub fn server_nonce() -> String {
+    generate_token()
+}
+
+/// The seed of a battle: the first 8 bytes (big-endian) of SHA-256 over
+/// `"{server_nonce}:{client_nonce}"`.
+pub fn battle_seed(server_nonce: &str, client_nonce: &str) -> u64 {
+    let digest = Sha256::digest(format!("{}:{}", server_nonce, client_nonce).as_bytes());
+    let mut seed = [0u8; 8];
+    seed.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(seed)
+}
+
+/// Printable ASCII without spaces, `MIN_NONCE_LEN` to `MAX_NONCE_LEN` long.
+pub fn is_valid_nonce(nonce: &str) -> bool {
+    (MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&nonce.len())
+        && nonce.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// 64 lowercase hex characters, as `commit` returns.
+pub fn is_valid_commitment(commitment: &str) -> bool {
+    commitment.len() == 64
+        && commitment
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_NONCE: &str = "2172b1bbd2984b1bac17aaf68e286086391d4a395d45d67e2a73f8082401d18c";
+    const CLIENT_NONCE: &str = "my-secret-nonce-0123456789";
+
+    #[test]
+    fn battle_seed_is_fixed_for_a_nonce_pair() {
+        assert_eq!(
+            battle_seed(SERVER_NONCE, CLIENT_NONCE),
+            10629141291573671705
+        );
+        // The order of the nonces matters
+        assert_ne!(
+            battle_seed(CLIENT_NONCE, SERVER_NONCE),
+            battle_seed(SERVER_NONCE, CLIENT_NONCE)
+        );
+    }
+
+    #[test]
+    fn commit_is_hex_sha256() {
+        assert_eq!(
+            commit(CLIENT_NONCE),
+            "1be2a20fbaa665f0691672b43a0c17c77ecc59588bc6abc4101492ea13e5379e"
+        );
+        assert_eq!(
+            commit(SERVER_NONCE),
+            "8fa1839d4469300427afee4981aa0b9bf49340a51005e29c1a8aacc631545747"
+        );
+        assert!(is_valid_commitment(&commit(CLIENT_NONCE)));
+    }
+
+    #[test]
+    fn server_nonces_are_valid_and_fresh() {
+        let nonce = server_nonce();
+        assert!(is_valid_nonce(&nonce));
+        assert_ne!(nonce, server_nonce());
+    }
+
+    #[test]
+    fn nonce_length_limits() {
+        assert!(!is_valid_nonce(&"a".repeat(MIN_NONCE_LEN - 1)));
+        assert!(is_valid_nonce(&"a".repeat(MIN_NONCE_LEN)));
+        assert!(is_valid_nonce(&"a".repeat(MAX_NONCE_LEN)));
+        assert!(!is_valid_nonce(&"a".repeat(MAX_NONCE_LEN + 1)));
+        assert!(!is_valid_nonce(""));
+    }
+
+    #[test]
+    fn nonce_characters() {
+        assert!(is_valid_nonce("!#$%&'()*+,-./:;<=>?@[]^_`{|}~"));
+        assert!(!is_valid_nonce("my secret nonce 0123"));
+        assert!(!is_valid_nonce("my-secret-nonce-0123\n"));
+        assert!(!is_valid_nonce("my-secret-nonce-0123\t"));
+        assert!(!is_valid_nonce("mein-geheimnis-ä-0123"));
+    }
+
+    #[test]
+    fn commitments_must_be_lowercase_hex() {
+        let valid = commit(CLIENT_NONCE);
+        assert!(is_valid_commitment(&valid));
+        assert!(!is_valid_commitment(&valid.to_uppercase()));
+        assert!(!is_valid_commitment(&valid.replacen('a', "A", 1)));
+        assert!(!is_valid_commitment(&valid.replacen('b', "g", 1)));
+        assert!(!is_valid_commitment(&valid[..63]));
+        assert!(!is_valid_commitment(&format!("{}0", valid)));
+        assert!(!is_valid_commitment(""));
+    }
+}
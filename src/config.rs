@@ -1,21 +1,838 @@
+// src/config.rs
+// Settings come from an optional TOML file (`CONFIG_FILE`, default `config.toml`),
+// then environment variables (and `.env`) override single values. See
+// `config.example.toml` for every setting and its variable.
 use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
 
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const DEFAULT_JWT_SECRET: &str = "secret";
+/// Shortest HS256 secret accepted in production; RFC 7518 asks for at least the hash size.
+const MIN_PRODUCTION_SECRET_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Invalid(String, String), // setting, problem
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "invalid {}: {}", path, e),
+            ConfigError::Invalid(setting, problem) => write!(f, "{}: {}", setting, problem),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid(setting: &str, problem: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid(setting.to_string(), problem.into())
+}
+
+/// `production` refuses to start with insecure defaults, set with `APP_ENV`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Development,
+    Production,
+}
+
+impl FromStr for Environment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "development" => Ok(Environment::Development),
+            "production" => Ok(Environment::Production),
+            other => Err(format!("expected development or production, got {}", other)),
+        }
+    }
+}
+
+/// Who must confirm their email address, set with `REQUIRE_EMAIL_VERIFICATION`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailVerification {
+    #[default]
+    Off, // unverified accounts can do everything
+    Login,  // unverified accounts can't log in
+    Battle, // unverified accounts can log in but not take part in battles
+}
+
+impl FromStr for EmailVerification {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(EmailVerification::Off),
+            "login" => Ok(EmailVerification::Login),
+            "battle" => Ok(EmailVerification::Battle),
+            other => Err(format!("expected off, login or battle, got {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    pub bind_addr: SocketAddr,
+    /// Where players reach this server, e.g. `https://play.example.com`. Actor IDs
+    /// and inbox URLs are built from it, and its host is the domain in usernames.
+    pub public_base_url: PublicUrl,
+    /// Reverse proxies whose `X-Forwarded-For` is believed; see `client_ip`.
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            trusted_proxies: TrustedProxies::default(),
+            public_base_url: PublicUrl {
+                base_url: "http://localhost:8080".to_string(),
+                domain: "localhost".to_string(),
+            },
+        }
+    }
+}
+
+/// Addresses of the reverse proxies in front of the server, shared as app data. Empty
+/// by default: the client IP is then always the address of the TCP peer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    /// A comma-separated list, e.g. `10.0.0.1,10.0.0.2`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| ip.parse().map_err(|_| format!("not an IP address: {}", ip)))
+            .collect::<Result<_, _>>()
+            .map(TrustedProxies)
+    }
+}
+
+/// The public address of the server, shared as app data.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PublicUrl {
+    pub base_url: String, // without trailing slash
+    pub domain: String,   // host part of `base_url`, appended to usernames
+}
+
+impl TryFrom<String> for PublicUrl {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl FromStr for PublicUrl {
+    type Err = String;
+
+    fn from_str(base_url: &str) -> Result<Self, Self::Err> {
+        let base_url = base_url.trim_end_matches('/');
+        let rest = base_url
+            .strip_prefix("https://")
+            .or_else(|| base_url.strip_prefix("http://"))
+            .ok_or("must start with http:// or https://")?;
+        if rest.contains('/') {
+            return Err("must not contain a path".to_string());
+        }
+        // Strip the port; IPv6 literals aren't usable in usernames anyway
+        let domain = rest.rsplit_once(':').map_or(rest, |(host, _)| host);
+        if domain.is_empty() || domain.contains(['[', ']', '@']) {
+            return Err("must contain a host name".to_string());
+        }
+
+        Ok(PublicUrl {
+            base_url: base_url.to_string(),
+            domain: domain.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl PublicUrl {
+    /// `alice` -> `alice@<domain>`
+    pub fn username(&self, local_part: &str) -> String {
+        format!("{}@{}", local_part, self.domain)
+    }
+
+    /// The ActivityPub actor ID of a (full) username.
+    pub fn actor_url(&self, username: &str) -> String {
+        format!("{}/actor/{}", self.base_url, username)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Database {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_seconds: u64,
+    pub slow_statement_ms: u64, // statements taking longer are logged as warnings
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database {
+            url: String::new(),
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_seconds: 30,
+            slow_statement_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Jwt {
+    /// HS256 signs with `secret`; RS256/EdDSA sign with the PEM key pair below
+    pub algorithm: String,
+    pub secret: String,
+    pub key_id: String,
+    pub private_key_path: Option<String>,
+    pub public_key_path: Option<String>,
+    /// Retired public keys still accepted for verification, by `kid`
+    pub verification_keys: BTreeMap<String, String>,
+}
+
+impl Default for Jwt {
+    fn default() -> Self {
+        Jwt {
+            algorithm: "HS256".to_string(),
+            secret: DEFAULT_JWT_SECRET.to_string(),
+            key_id: "main".to_string(),
+            private_key_path: None,
+            public_key_path: None,
+            verification_keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// How long issued tokens stay valid, shared as app data.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenLifetimes {
+    pub access_minutes: u32,
+    pub mfa_minutes: u32, // between the password and the 2FA step of login
+    pub refresh_days: u32,
+    pub password_reset_minutes: u32,
+    pub email_verification_hours: u32,
+}
+
+impl Default for TokenLifetimes {
+    fn default() -> Self {
+        TokenLifetimes {
+            access_minutes: 60,
+            mfa_minutes: 5,
+            refresh_days: 14,
+            password_reset_minutes: 60,
+            email_verification_hours: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mail {
+    /// "spool" writes emails to `spool_dir`, "smtp" delivers them to `smtp_host`
+    pub transport: String,
+    pub from: String,
+    pub spool_dir: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+impl Default for Mail {
+    fn default() -> Self {
+        Mail {
+            transport: "spool".to_string(),
+            from: "noreply@localhost".to_string(),
+            spool_dir: "mail_spool".to_string(),
+            smtp_host: "localhost".to_string(),
+            smtp_port: 1025,
+            smtp_username: None,
+            smtp_password: None,
+        }
+    }
+}
+
+/// Argon2id costs for new password hashes; defaults are the argon2 crate's.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordHashing {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashing {
+    fn default() -> Self {
+        PasswordHashing {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// How long a deleted account can still be restored, set with `ACCOUNT_DELETION_GRACE_DAYS`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountDeletion {
+    pub grace_period_days: u32,
+}
+
+impl Default for AccountDeletion {
+    fn default() -> Self {
+        AccountDeletion {
+            grace_period_days: 30,
+        }
+    }
+}
+
+/// Battle settings; the unit stats themselves are in the ruleset file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Simulator {
+    pub ruleset: Option<String>, // path to a ruleset TOML file; the built-in one if unset
+    pub max_rounds: u32,         // the battle is decided on remaining units after this many rounds
+    pub commitment_minutes: u32, // how long a seed commitment can be used to start a battle
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Simulator {
+            ruleset: None,
+            max_rounds: 1000,
+            commitment_minutes: 10,
+        }
+    }
+}
+
+/// Optional parts of the API; disabled routes answer 404.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    pub guest_accounts: bool,
+    pub api_keys: bool, // also stops existing keys from authenticating
+    pub websocket: bool,
+    pub sse: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            guest_accounts: true,
+            api_keys: true,
+            websocket: true,
+            sse: true,
+        }
+    }
+}
+
+/// Shape of log lines, set with `LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text, // human-readable, for development
+    Json, // one object per line, for log shippers
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("expected text or json, got {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    pub format: LogFormat,
+    /// `RUST_LOG` syntax, e.g. `info,sqlx::query=debug`.
+    pub filter: String,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            format: LogFormat::Text,
+            filter: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    pub database_url: String,
-    pub jwt_secret: String,
+    pub environment: Environment,
+    pub email_verification: EmailVerification,
+    pub server: Server,
+    pub database: Database,
+    pub jwt: Jwt,
+    pub tokens: TokenLifetimes,
+    pub mail: Mail,
+    pub password_hashing: PasswordHashing,
+    pub account_deletion: AccountDeletion,
+    pub simulator: Simulator,
+    pub features: Features,
+    pub logging: Logging,
 }
 
 impl Config {
-    pub fn from_env() -> Self {
+    /// Read the config file and environment and check the result.
+    pub fn load() -> Result<Self, ConfigError> {
         dotenv().ok();
 
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
-        let jwt_secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
+        let mut config = match env::var("CONFIG_FILE") {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            Err(_) => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_string(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override(&mut self.environment, "APP_ENV")?;
+        env_override(&mut self.email_verification, "REQUIRE_EMAIL_VERIFICATION")?;
+
+        env_override(&mut self.server.bind_addr, "BIND_ADDR")?;
+        env_override(&mut self.server.public_base_url, "PUBLIC_BASE_URL")?;
+        env_override(&mut self.server.trusted_proxies, "TRUSTED_PROXIES")?;
+
+        env_override(&mut self.database.url, "DATABASE_URL")?;
+        env_override(
+            &mut self.database.max_connections,
+            "DATABASE_MAX_CONNECTIONS",
+        )?;
+        env_override(
+            &mut self.database.min_connections,
+            "DATABASE_MIN_CONNECTIONS",
+        )?;
+        env_override(
+            &mut self.database.acquire_timeout_seconds,
+            "DATABASE_ACQUIRE_TIMEOUT_SECONDS",
+        )?;
+        env_override(
+            &mut self.database.slow_statement_ms,
+            "DATABASE_SLOW_STATEMENT_MS",
+        )?;
+
+        env_override(&mut self.jwt.algorithm, "JWT_ALGORITHM")?;
+        env_override(&mut self.jwt.secret, "JWT_SECRET")?;
+        env_override(&mut self.jwt.key_id, "JWT_KEY_ID")?;
+        env_override_optional(&mut self.jwt.private_key_path, "JWT_PRIVATE_KEY_PATH");
+        env_override_optional(&mut self.jwt.public_key_path, "JWT_PUBLIC_KEY_PATH");
+        // "kid=path,kid=path"
+        if let Ok(value) = env::var("JWT_VERIFICATION_KEYS") {
+            self.jwt.verification_keys = parse_key_list(&value);
+        }
+
+        env_override(&mut self.tokens.access_minutes, "ACCESS_TOKEN_MINUTES")?;
+        env_override(&mut self.tokens.mfa_minutes, "MFA_TOKEN_MINUTES")?;
+        env_override(&mut self.tokens.refresh_days, "REFRESH_TOKEN_DAYS")?;
+        env_override(
+            &mut self.tokens.password_reset_minutes,
+            "PASSWORD_RESET_TOKEN_MINUTES",
+        )?;
+        env_override(
+            &mut self.tokens.email_verification_hours,
+            "EMAIL_VERIFICATION_TOKEN_HOURS",
+        )?;
+
+        env_override(&mut self.mail.transport, "MAIL_TRANSPORT")?;
+        env_override(&mut self.mail.from, "MAIL_FROM")?;
+        env_override(&mut self.mail.spool_dir, "MAIL_SPOOL_DIR")?;
+        env_override(&mut self.mail.smtp_host, "SMTP_HOST")?;
+        env_override(&mut self.mail.smtp_port, "SMTP_PORT")?;
+        env_override_optional(&mut self.mail.smtp_username, "SMTP_USERNAME");
+        env_override_optional(&mut self.mail.smtp_password, "SMTP_PASSWORD");
+
+        env_override(&mut self.password_hashing.memory_kib, "ARGON2_MEMORY_KIB")?;
+        env_override(&mut self.password_hashing.iterations, "ARGON2_ITERATIONS")?;
+        env_override(&mut self.password_hashing.parallelism, "ARGON2_PARALLELISM")?;
+
+        env_override(
+            &mut self.account_deletion.grace_period_days,
+            "ACCOUNT_DELETION_GRACE_DAYS",
+        )?;
+
+        env_override_optional(&mut self.simulator.ruleset, "SIMULATOR_RULESET");
+        env_override(&mut self.simulator.max_rounds, "SIMULATOR_MAX_ROUNDS")?;
+        env_override(
+            &mut self.simulator.commitment_minutes,
+            "SIMULATOR_COMMITMENT_MINUTES",
+        )?;
+
+        env_override(&mut self.features.guest_accounts, "FEATURE_GUEST_ACCOUNTS")?;
+        env_override(&mut self.features.api_keys, "FEATURE_API_KEYS")?;
+        env_override(&mut self.features.websocket, "FEATURE_WEBSOCKET")?;
+        env_override(&mut self.features.sse, "FEATURE_SSE")?;
+
+        env_override(&mut self.logging.format, "LOG_FORMAT")?;
+        env_override(&mut self.logging.filter, "RUST_LOG")?;
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.database.url.is_empty() {
+            return Err(invalid(
+                "database.url",
+                "must be set (or DATABASE_URL in the environment)",
+            ));
+        }
+        if self.database.max_connections == 0 {
+            return Err(invalid("database.max_connections", "must be at least 1"));
+        }
+        if self.database.min_connections > self.database.max_connections {
+            return Err(invalid(
+                "database.min_connections",
+                "must not exceed database.max_connections",
+            ));
+        }
+
+        let tokens = &self.tokens;
+        for (setting, value) in [
+            ("tokens.access_minutes", tokens.access_minutes),
+            ("tokens.mfa_minutes", tokens.mfa_minutes),
+            ("tokens.refresh_days", tokens.refresh_days),
+            (
+                "tokens.password_reset_minutes",
+                tokens.password_reset_minutes,
+            ),
+            (
+                "tokens.email_verification_hours",
+                tokens.email_verification_hours,
+            ),
+        ] {
+            if value == 0 {
+                return Err(invalid(setting, "must be at least 1"));
+            }
+        }
+
+        match self.jwt.algorithm.as_str() {
+            "HS256" => {}
+            "RS256" | "EdDSA" => {
+                for (setting, path) in [
+                    ("jwt.private_key_path", &self.jwt.private_key_path),
+                    ("jwt.public_key_path", &self.jwt.public_key_path),
+                ] {
+                    match path {
+                        None => {
+                            return Err(invalid(
+                                setting,
+                                format!("must be set for {}", self.jwt.algorithm),
+                            ));
+                        }
+                        Some(path) if !Path::new(path).is_file() => {
+                            return Err(invalid(setting, format!("no such file: {}", path)));
+                        }
+                        Some(_) => {}
+                    }
+                }
+                for (kid, path) in &self.jwt.verification_keys {
+                    if !Path::new(path).is_file() {
+                        return Err(invalid(
+                            "jwt.verification_keys",
+                            format!("no such file for '{}': {}", kid, path),
+                        ));
+                    }
+                }
+            }
+            other => {
+                return Err(invalid(
+                    "jwt.algorithm",
+                    format!("expected HS256, RS256 or EdDSA, got {}", other),
+                ));
+            }
+        }
+
+        let hashing = &self.password_hashing;
+        if let Err(e) = argon2::Params::new(
+            hashing.memory_kib,
+            hashing.iterations,
+            hashing.parallelism,
+            None,
+        ) {
+            return Err(invalid("password_hashing", e.to_string()));
+        }
+
+        match self.mail.transport.as_str() {
+            "spool" => {}
+            "smtp" => {
+                if let Err(e) = self.mail.from.parse::<lettre::message::Mailbox>() {
+                    return Err(invalid("mail.from", e.to_string()));
+                }
+                if self.mail.smtp_host.is_empty() {
+                    return Err(invalid("mail.smtp_host", "must be set for smtp"));
+                }
+                if self.mail.smtp_port == 0 {
+                    return Err(invalid("mail.smtp_port", "must not be 0"));
+                }
+                if self.mail.smtp_username.is_some() != self.mail.smtp_password.is_some() {
+                    return Err(invalid(
+                        "mail.smtp_username",
+                        "smtp_username and smtp_password must be set together",
+                    ));
+                }
+            }
+            _ => return Err(invalid("mail.transport", "expected spool or smtp")),
+        }
+
+        if self.simulator.max_rounds == 0 {
+            return Err(invalid("simulator.max_rounds", "must be at least 1"));
+        }
+        if self.simulator.commitment_minutes == 0 {
+            return Err(invalid(
+                "simulator.commitment_minutes",
+                "must be at least 1",
+            ));
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.logging.filter) {
+            return Err(invalid("logging.filter", e.to_string()));
+        }
+
+        if self.environment == Environment::Production && self.jwt.algorithm == "HS256" {
+            if self.jwt.secret == DEFAULT_JWT_SECRET {
+                return Err(invalid(
+                    "jwt.secret",
+                    "the default secret is not allowed in production",
+                ));
+            }
+            if self.jwt.secret.len() < MIN_PRODUCTION_SECRET_LEN {
+                return Err(invalid(
+                    "jwt.secret",
+                    format!(
+                        "must be at least {} bytes in production",
+                        MIN_PRODUCTION_SECRET_LEN
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace `target` with the parsed value of the variable `name`, if set.
+fn env_override<T>(target: &mut T, name: &str) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = env::var(name) {
+        *target = value
+            .trim()
+            .parse()
+            .map_err(|e: T::Err| invalid(name, format!("cannot parse {:?}: {}", value, e)))?;
+    }
+    Ok(())
+}
+
+fn env_override_optional(target: &mut Option<String>, name: &str) {
+    if let Ok(value) = env::var(name) {
+        *target = Some(value);
+    }
+}
+
+fn parse_key_list(value: &str) -> BTreeMap<String, String> {
+    value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .map(|(kid, path)| (kid.trim().to_string(), path.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The defaults plus the one setting without a usable default.
+    fn valid_config() -> Config {
+        let mut config = Config::default();
+        config.database.url = "postgres://localhost/test".to_string();
+        config
+    }
+
+    fn invalid_setting(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(setting, _)) => setting,
+            other => panic!("expected an invalid setting, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_with_a_database_are_valid() {
+        valid_config().validate().unwrap();
+        assert_eq!(invalid_setting(&Config::default()), "database.url");
+    }
+
+    #[test]
+    fn default_secret_is_refused_in_production() {
+        let mut config = valid_config();
+        config.environment = Environment::Production;
+        assert_eq!(invalid_setting(&config), "jwt.secret");
+
+        config.jwt.secret = "short".to_string();
+        assert_eq!(invalid_setting(&config), "jwt.secret");
+
+        config.jwt.secret = "x".repeat(MIN_PRODUCTION_SECRET_LEN);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn key_pair_algorithms_need_key_files() {
+        let mut config = valid_config();
+        config.jwt.algorithm = "ES256".to_string();
+        assert_eq!(invalid_setting(&config), "jwt.algorithm");
+
+        config.jwt.algorithm = "EdDSA".to_string();
+        assert_eq!(invalid_setting(&config), "jwt.private_key_path");
+
+        // Any existing file passes here; the key itself is checked when it is loaded.
+        config.jwt.private_key_path = Some("Cargo.toml".to_string());
+        config.jwt.public_key_path = Some("missing.pub".to_string());
+        assert_eq!(invalid_setting(&config), "jwt.public_key_path");
+
+        config.jwt.public_key_path = Some("Cargo.toml".to_string());
+        config.validate().unwrap();
+
+        config
+            .jwt
+            .verification_keys
+            .insert("old".to_string(), "missing.pub".to_string());
+        assert_eq!(invalid_setting(&config), "jwt.verification_keys");
+    }
+
+    #[test]
+    fn argon2_costs_are_bounded() {
+        let mut config = valid_config();
+        config.password_hashing.iterations = 0;
+        assert_eq!(invalid_setting(&config), "password_hashing");
+
+        config.password_hashing.iterations = 2;
+        config.password_hashing.parallelism = 4;
+        config.password_hashing.memory_kib = 16; // below 8 KiB per lane
+        assert_eq!(invalid_setting(&config), "password_hashing");
+    }
+
+    #[test]
+    fn smtp_settings_are_checked() {
+        let mut config = valid_config();
+        config.mail.transport = "sendmail".to_string();
+        assert_eq!(invalid_setting(&config), "mail.transport");
+
+        config.mail.transport = "smtp".to_string();
+        config.validate().unwrap();
+
+        config.mail.from = "not an address".to_string();
+        assert_eq!(invalid_setting(&config), "mail.from");
+
+        config.mail.from = "Game <noreply@example.com>".to_string();
+        config.mail.smtp_username = Some("mailer".to_string());
+        assert_eq!(invalid_setting(&config), "mail.smtp_username");
+
+        config.mail.smtp_password = Some("hunter2".to_string());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn env_override_parses_or_names_the_variable() {
+        let mut port: u16 = 1025;
+        env_override(&mut port, "CONFIG_TEST_UNSET_PORT").unwrap();
+        assert_eq!(port, 1025);
+
+        // SAFETY: no other test reads or writes these variables.
+        unsafe {
+            env::set_var("CONFIG_TEST_PORT", " 2525 ");
+            env::set_var("CONFIG_TEST_BAD_PORT", "smtp");
+        }
+        env_override(&mut port, "CONFIG_TEST_PORT").unwrap();
+        assert_eq!(port, 2525);
+
+        match env_override(&mut port, "CONFIG_TEST_BAD_PORT") {
+            Err(ConfigError::Invalid(setting, _)) => assert_eq!(setting, "CONFIG_TEST_BAD_PORT"),
+            other => panic!("expected an invalid setting, got {:?}", other),
+        }
+        assert_eq!(port, 2525);
+    }
+
+    #[test]
+    fn key_list_is_parsed() {
+        let keys = parse_key_list(" 2024-01 = keys/a.pub ,broken, 2024-06=keys/b.pub");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys["2024-01"], "keys/a.pub");
+        assert_eq!(keys["2024-06"], "keys/b.pub");
+    }
+
+    #[test]
+    fn public_url_is_parsed() {
+        let url: PublicUrl = "https://Game.Example.com:8443/".parse().unwrap();
+        assert_eq!(url.base_url, "https://Game.Example.com:8443");
+        assert_eq!(url.domain, "game.example.com");
+        assert_eq!(url.username("alice"), "alice@game.example.com");
+        assert_eq!(
+            url.actor_url("alice@game.example.com"),
+            "https://Game.Example.com:8443/actor/alice@game.example.com"
+        );
 
-        Config {
-            database_url,
-            jwt_secret,
+        for bad in [
+            "game.example.com",
+            "ftp://game.example.com",
+            "https://game.example.com/api",
+            "https://",
+            "https://user@game.example.com",
+            "http://[::1]:8080",
+        ] {
+            assert!(bad.parse::<PublicUrl>().is_err(), "{bad}");
         }
     }
 }
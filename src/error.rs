@@ -0,0 +1,157 @@
+// src/error.rs
+// The error type of every handler. Rendered as RFC 7807 `application/problem+json`:
+// {"type": "about:blank", "title": "Not Found", "status": 404, "detail": "User not found"}
+// plus `errors`, `field` or `retry_after` where they apply.
+use crate::auth::password::PasswordError;
+use crate::validation::ValidationErrors;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{CONTENT_TYPE, RETRY_AFTER};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict {
+        field: Option<&'static str>, // the input that clashed, if known
+        message: String,
+    },
+    Validation(ValidationErrors),
+    TooManyRequests {
+        retry_after: u64, // seconds
+    },
+    /// Logged server-side; clients only see a generic message.
+    Internal(String),
+}
+
+impl AppError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        AppError::BadRequest(message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        AppError::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError::Forbidden(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict {
+            field: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl fmt::Display) -> Self {
+        AppError::Internal(message.to_string())
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict { message, .. } => f.write_str(message),
+            AppError::Validation(errors) => write!(f, "{}", errors),
+            AppError::TooManyRequests { retry_after } => write!(
+                f,
+                "Too many failed login attempts, retry in {} seconds",
+                retry_after
+            ),
+            AppError::Internal(_) => f.write_str("Internal server error"),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut problem = serde_json::json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or("Error"),
+            "status": status.as_u16(),
+            "detail": self.to_string(),
+        });
+
+        let mut response = HttpResponse::build(status);
+        response.insert_header((CONTENT_TYPE, "application/problem+json"));
+
+        match self {
+            AppError::Conflict {
+                field: Some(field), ..
+            } => problem["field"] = (*field).into(),
+            AppError::Validation(errors) => problem["errors"] = serde_json::json!(errors.fields()),
+            AppError::TooManyRequests { retry_after } => {
+                problem["retry_after"] = (*retry_after).into();
+                response.insert_header((RETRY_AFTER, retry_after.to_string()));
+            }
+            AppError::Internal(message) => tracing::error!("{}", message),
+            _ => {}
+        }
+
+        response.json(problem)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => AppError::not_found("Resource not found"),
+            sqlx::Error::Database(db) if db.is_unique_violation() => match db.constraint() {
+                Some(c) if c.starts_with("users_username") => AppError::Conflict {
+                    field: Some("username"),
+                    message: "Username is already taken".to_string(),
+                },
+                Some(c) if c.starts_with("users_email") => AppError::Conflict {
+                    field: Some("email"),
+                    message: "Email is already registered".to_string(),
+                },
+                _ => AppError::conflict("Resource already exists"),
+            },
+            _ => AppError::Internal(format!("Database error: {}", e)),
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::Validation(errors)
+    }
+}
+
+impl From<PasswordError> for AppError {
+    fn from(e: PasswordError) -> Self {
+        AppError::internal(e)
+    }
+}
+
+/// Error handler for the `Json`, `Path` and `Query` extractors, so malformed
+/// requests get a problem document too.
+pub fn bad_request_handler<E: fmt::Display>(err: E, _req: &HttpRequest) -> actix_web::Error {
+    AppError::bad_request(err.to_string()).into()
+}
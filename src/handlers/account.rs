@@ -0,0 +1,293 @@
+use crate::auth::extractor::AuthenticatedUser;
+use crate::auth::password::{Hasher, PasswordError};
+use crate::auth::throttle;
+use crate::config::AccountDeletion;
+use crate::error::AppError;
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct DeleteAccountDto {
+    pub password: Option<String>, // required unless the account is a guest
+}
+
+#[derive(Serialize)]
+struct FleetExport {
+    id: i32,
+    ships: Option<i32>,
+    fighters: Option<i32>,
+    bombers: Option<i32>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct MessageExport {
+    id: i32,
+    sender: Uuid,
+    recipient: Uuid,
+    content: String,
+    activity_type: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct BattleExport {
+    id: Uuid,
+    player_a: Option<Uuid>,
+    player_b: Option<Uuid>,
+    #[serde(serialize_with = "seed_as_u64")]
+    seed: i64,
+    ruleset_name: Option<String>,
+    ruleset_version: Option<i32>,
+    fleets_before: Option<serde_json::Value>,
+    fleets_after: Option<serde_json::Value>,
+    winner: String,
+    rounds: i32,
+    combat_log: serde_json::Value,
+    client_commitment: Option<String>,
+    client_nonce: Option<String>,
+    server_commitment: Option<String>,
+    server_nonce: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// Seeds are u64s stored bit for bit in a BIGINT; export them as the players sent them.
+fn seed_as_u64<S: serde::Serializer>(seed: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(*seed as u64)
+}
+
+/// Schedule the account for deletion after the grace period, end all sessions and
+/// revoke all API keys. Logging in again and calling `/me/cancel-deletion` keeps the
+/// account; revoked keys stay revoked.
+pub async fn delete_account(
+    pool: web::Data<PgPool>,
+    hasher: web::Data<Hasher>,
+    account_deletion: web::Data<AccountDeletion>,
+    auth_user: AuthenticatedUser,
+    form: web::Json<DeleteAccountDto>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let user = sqlx::query!("SELECT password FROM users WHERE id = $1", auth_user.id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| AppError::unauthorized("User not found"))?;
+
+    // Guests have no password to confirm with
+    if let Some(hash) = user.password.as_deref() {
+        let password = form.password.as_deref().unwrap_or_default();
+        match hasher.verify_password(hash, password) {
+            Ok(_) => {}
+            Err(PasswordError::Mismatch) => return Err(AppError::forbidden("Invalid password")),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let delete_at = Utc::now() + Duration::days(account_deletion.grace_period_days.into());
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE users SET deletion_scheduled_at = $1 WHERE id = $2",
+        delete_at,
+        auth_user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+        auth_user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM api_keys WHERE user_id = $1", auth_user.id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "deletion_scheduled_at": delete_at,
+    })))
+}
+
+/// Keep an account whose deletion is still pending.
+pub async fn cancel_account_deletion(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET deletion_scheduled_at = NULL
+        WHERE id = $1 AND deletion_scheduled_at IS NOT NULL
+        "#,
+        auth_user.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found("No deletion scheduled"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Everything stored about the authenticated user as one JSON document.
+pub async fn export_account(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let profile = sqlx::query!(
+        r#"
+        SELECT username, email, roles as "roles!", email_verified_at, totp_enabled_at,
+          deletion_scheduled_at, created_at
+        FROM users
+        WHERE id = $1
+        "#,
+        auth_user.id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::unauthorized("User not found"))?;
+
+    let fleets = sqlx::query_as!(
+        FleetExport,
+        r#"
+        SELECT id, ships, fighters, bombers, created_at
+        FROM fleets
+        WHERE user_id = $1
+        ORDER BY id
+        "#,
+        auth_user.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let messages = sqlx::query_as!(
+        MessageExport,
+        r#"
+        SELECT id, sender, recipient, content, activity_type, created_at
+        FROM messages
+        WHERE sender = $1 OR recipient = $1
+        ORDER BY id
+        "#,
+        auth_user.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let battle_history = sqlx::query_as!(
+        BattleExport,
+        r#"
+        SELECT id, player_a, player_b, seed, ruleset_name, ruleset_version, fleets_before,
+          fleets_after, winner, rounds, combat_log, client_commitment, client_nonce,
+          server_commitment, server_nonce, created_at
+        FROM battles
+        WHERE player_a = $1 OR player_b = $1
+        ORDER BY created_at, id
+        "#,
+        auth_user.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"account-export.json\"",
+        ))
+        .json(serde_json::json!({
+            "exported_at": Utc::now(),
+            "profile": {
+                "id": auth_user.id,
+                "username": profile.username,
+                "email": profile.email,
+                "roles": profile.roles,
+                "email_verified_at": profile.email_verified_at,
+                "two_factor_enabled": profile.totp_enabled_at.is_some(),
+                "deletion_scheduled_at": profile.deletion_scheduled_at,
+                "created_at": profile.created_at,
+            },
+            "fleets": fleets,
+            "messages": messages,
+            "battle_history": battle_history,
+        })))
+}
+
+/// Whether `username` (with domain) belonged to a hard-deleted account. Its Tombstone
+/// keeps the name, so it can't be handed to someone else.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn username_was_deleted(pool: &PgPool, username: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM deleted_users WHERE lower(username) = lower($1)) as "exists!""#,
+        username
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(deleted)
+}
+
+/// Hard-delete accounts whose grace period is over. Fleets, messages, tokens and
+/// keys go with them through `ON DELETE CASCADE`; the username is kept as a Tombstone.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn purge_deleted_accounts(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let users = sqlx::query!(
+        r#"
+        SELECT id, username, email
+        FROM users
+        WHERE deletion_scheduled_at <= now()
+        FOR UPDATE SKIP LOCKED
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for user in &users {
+        sqlx::query!(
+            "INSERT INTO deleted_users (username) VALUES ($1) ON CONFLICT (username) DO NOTHING",
+            user.username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // The login audit trail keeps the email address otherwise
+        sqlx::query!("DELETE FROM login_failures WHERE user_id = $1", user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        if let Some(email) = &user.email {
+            throttle::forget_account(&mut *tx, email).await?;
+        }
+
+        sqlx::query!("DELETE FROM users WHERE id = $1", user.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(users.len() as u64)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/me", web::delete().to(delete_account));
+    cfg.route(
+        "/me/cancel-deletion",
+        web::post().to(cancel_account_deletion),
+    );
+    cfg.route("/me/export", web::get().to(export_account));
+}
@@ -1,8 +1,14 @@
 use std::fmt::Debug;
 
-use crate::handlers::simulator::{BattleRequestActivity, handle_battle_request};
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::config::{EmailVerification, PublicUrl};
+use crate::error::AppError;
+use crate::handlers::simulator::{BattleLogQuery, BattleRequestActivity, handle_battle_request};
+use crate::metrics::Metrics;
 use crate::models::activity_pub::Activity;
-use actix_web::{HttpResponse, Responder, web};
+use crate::ruleset::Ruleset;
+use actix_web::{HttpResponse, web};
 use serde_json::json;
 use sqlx::PgPool;
 use sqlx::types::Uuid;
@@ -17,69 +23,109 @@ struct SentMessage {
     activity_type: String,
 }
 
-pub async fn get_actor(username: web::Path<String>, pool: web::Data<PgPool>) -> impl Responder {
+pub async fn get_actor(
+    auth_user: AuthenticatedUser,
+    username: web::Path<String>,
+    pool: web::Data<PgPool>,
+    public_url: web::Data<PublicUrl>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Profile)?;
+
+    let username = username.into_inner();
     let actor = sqlx::query!(
-        "SELECT username FROM users WHERE username = $1",
-        username.into_inner()
+        "SELECT username FROM users WHERE lower(username) = lower($1)",
+        username
     )
     .fetch_optional(pool.get_ref())
-    .await;
-
-    if let Ok(Some(user)) = actor {
-        HttpResponse::Ok().json(json!({
-            "@context": "https://www.w3.org/ns/activitystreams",
-            "id": format!("http://localhost/actor/{}", user.username),
-            "type": "Person",
-            "preferredUsername": user.username,
-            "name": user.username,
-            "inbox": format!("http://localhost/actor/{}/inbox", user.username),
-            "outbox": format!("http://localhost/actor/{}/outbox", user.username),
-            "followers": format!("http://localhost/actor/{}/followers", user.username),
-            "following": format!("http://localhost/actor/{}/following", user.username),
-            "publicKey": {
-                "id": format!("http://localhost/actor/{}#main-key", user.username),
-                "owner": format!("http://localhost/actor/{}", user.username),
-                "publicKeyPem": "-----BEGIN PUBLIC KEY-----\n...\n-----END PUBLIC KEY-----"
-            }
-        }))
-    } else {
-        HttpResponse::NotFound().finish()
-    }
+    .await?;
+
+    let Some(user) = actor else {
+        return tombstone(&pool, &public_url, &username).await;
+    };
+
+    let actor_url = public_url.actor_url(&user.username);
+    Ok(HttpResponse::Ok().json(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": actor_url,
+        "type": "Person",
+        "preferredUsername": user.username,
+        "name": user.username,
+        "inbox": format!("{}/inbox", actor_url),
+        "outbox": format!("{}/outbox", actor_url),
+        "followers": format!("{}/followers", actor_url),
+        "following": format!("{}/following", actor_url),
+        "publicKey": {
+            "id": format!("{}#main-key", actor_url),
+            "owner": actor_url,
+            "publicKeyPem": "-----BEGIN PUBLIC KEY-----\n...\n-----END PUBLIC KEY-----"
+        }
+    })))
+}
+
+/// 410 with a `Tombstone` for deleted accounts, 404 for names that never existed.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+async fn tombstone(
+    pool: &PgPool,
+    public_url: &PublicUrl,
+    username: &str,
+) -> Result<HttpResponse, AppError> {
+    let deleted = sqlx::query!(
+        "SELECT username, deleted_at FROM deleted_users WHERE lower(username) = lower($1)",
+        username
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    Ok(HttpResponse::Gone().json(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": public_url.actor_url(&deleted.username),
+        "type": "Tombstone",
+        "formerType": "Person",
+        "deleted": deleted.deleted_at,
+    })))
 }
 
 pub async fn inbox(
+    auth_user: AuthenticatedUser,
     activity: web::Json<Activity>,
     pool: web::Data<PgPool>,
-) -> Result<HttpResponse, actix_web::Error> {
+    email_verification: web::Data<EmailVerification>,
+    ruleset: web::Data<Ruleset>,
+    metrics: web::Data<Metrics>,
+    query: web::Query<BattleLogQuery>,
+) -> Result<HttpResponse, AppError> {
+    // Activities are delivered on behalf of their actor
+    auth_user.require_scope(Scope::Battles)?;
+    auth_user.authorize_for(activity.actor)?;
+    metrics.record_inbox_activity(&activity.activity_type);
+
     match activity.activity_type.as_str() {
         "BattleRequest" => {
-            // Insert BattleRequest into messages table for logging
-            sqlx::query!(
-                "INSERT INTO messages (sender, recipient, content, activity_type) VALUES ($1, $2, $3, $4)",
-                activity.actor,
-                activity.object,
-                Some("Battle initiated".to_string()),
-                activity.activity_type
-            )
-            .execute(pool.get_ref())
-            .await
-            .map_err(|e| {
-                eprintln!("Error logging BattleRequest: {:?}", e);
-                actix_web::error::ErrorInternalServerError("Failed to log BattleRequest")
-            })?;
-
-            if let (Some(fleet), Some(seed)) = (&activity.fleet, activity.seed) {
+            if let (Some(fleet), Some(commitment_id), Some(nonce)) =
+                (&activity.fleet, activity.commitment_id, &activity.nonce)
+            {
                 let battle_request = BattleRequestActivity {
                     activity_type: activity.activity_type.clone(),
-                    actor: activity.actor.clone(),
-                    target: activity.object.clone(),
+                    actor: activity.actor,
+                    target: activity.object,
                     fleet: fleet.clone(),
-                    seed,
+                    commitment_id,
+                    nonce: nonce.clone(),
                 };
 
-                handle_battle_request(web::Json(battle_request), pool).await
+                receive_battle_request(
+                    battle_request,
+                    pool,
+                    email_verification,
+                    ruleset,
+                    metrics,
+                    auth_user.id,
+                    query.log,
+                )
+                .await
             } else {
-                Ok(HttpResponse::BadRequest().body("Invalid BattleRequest payload"))
+                Err(AppError::bad_request("Invalid BattleRequest payload"))
             }
         }
         "Message" => {
@@ -92,58 +138,82 @@ pub async fn inbox(
                 activity.activity_type
             )
             .execute(pool.get_ref())
-            .await
-            .map_err(|e| {
-                eprintln!("Error storing activity: {:?}", e);
-                actix_web::error::ErrorInternalServerError("Failed to store activity")
-            })?;
+            .await?;
 
             Ok(HttpResponse::Ok().body("Activity received and stored"))
         }
-        _ => Ok(HttpResponse::BadRequest().body("Unsupported activity type")),
+        _ => Err(AppError::bad_request("Unsupported activity type")),
     }
 }
 
-pub async fn outbox(username: web::Path<String>, pool: web::Data<PgPool>) -> impl Responder {
-    log::info!("Fetching outbox for username: {}", username);
+/// Log a battle request in the messages table and fight it, seeded by the sender's
+/// commitment. Used by the inbox and by `/battle-request/`, whose targets are local.
+pub async fn receive_battle_request(
+    battle_request: BattleRequestActivity,
+    pool: web::Data<PgPool>,
+    email_verification: web::Data<EmailVerification>,
+    ruleset: web::Data<Ruleset>,
+    metrics: web::Data<Metrics>,
+    committed_by: Uuid,
+    include_log: bool,
+) -> Result<HttpResponse, AppError> {
+    sqlx::query!(
+        "INSERT INTO messages (sender, recipient, content, activity_type) VALUES ($1, $2, $3, $4)",
+        battle_request.actor,
+        battle_request.target,
+        Some("Battle initiated".to_string()),
+        battle_request.activity_type
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    handle_battle_request(
+        web::Json(battle_request),
+        pool,
+        email_verification,
+        ruleset,
+        metrics,
+        committed_by,
+        include_log,
+    )
+    .await
+}
+
+pub async fn outbox(
+    auth_user: AuthenticatedUser,
+    username: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Profile)?;
+
+    tracing::info!(%username, "Fetching outbox");
 
     // Fetch the user's ID from the username
-    let user_id_result = sqlx::query_scalar!(
-        "SELECT id FROM users WHERE username = $1",
+    let user_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE lower(username) = lower($1)",
         username.as_str()
     )
     .fetch_optional(pool.get_ref())
-    .await;
-
-    if let Ok(Some(user_id)) = user_id_result {
-        let rows = sqlx::query!(
-            "SELECT recipient, content, created_at, activity_type FROM messages WHERE sender = $1",
-            user_id
-        )
-        .fetch_all(pool.get_ref())
-        .await;
-
-        match rows {
-            Ok(rows) => {
-                log::info!("Fetched {} messages from outbox.", rows.len());
-                let messages: Vec<SentMessage> = rows
-                    .into_iter()
-                    .map(|row| SentMessage {
-                        recipient: row.recipient,
-                        content: row.content,
-                        created_at: row.created_at.map(|dt| dt.to_string()),
-                        activity_type: row.activity_type,
-                    })
-                    .collect();
-                HttpResponse::Ok().json(messages)
-            }
-            Err(e) => {
-                log::error!("Error fetching outbox: {:?}", e);
-                HttpResponse::InternalServerError().body("Failed to fetch outbox")
-            }
-        }
-    } else {
-        log::error!("User with username '{}' not found.", username);
-        HttpResponse::NotFound().body("User not found")
-    }
+    .await?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    let rows = sqlx::query!(
+        "SELECT recipient, content, created_at, activity_type FROM messages WHERE sender = $1",
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    tracing::info!(count = rows.len(), "Fetched outbox messages");
+    let messages: Vec<SentMessage> = rows
+        .into_iter()
+        .map(|row| SentMessage {
+            recipient: row.recipient,
+            content: row.content,
+            created_at: row.created_at.map(|dt| dt.to_string()),
+            activity_type: row.activity_type,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(messages))
 }
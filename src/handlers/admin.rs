@@ -0,0 +1,44 @@
+use crate::auth::roles::Role;
+use crate::error::AppError;
+use crate::middleware::require_role::RequireRole;
+use actix_web::{HttpResponse, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct SetRolesDto {
+    pub roles: Vec<Role>,
+}
+
+/// Replace the roles of a user. Takes effect on the user's next access token.
+pub async fn set_user_roles(
+    pool: web::Data<PgPool>,
+    user_id: web::Path<Uuid>,
+    form: web::Json<SetRolesDto>,
+) -> Result<HttpResponse, AppError> {
+    let roles: Vec<String> = form.roles.iter().map(|r| r.to_string()).collect();
+
+    let user = sqlx::query!(
+        "UPDATE users SET roles = $1 WHERE id = $2 RETURNING id, username, roles",
+        &roles,
+        user_id.into_inner()
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": user.id,
+        "username": user.username,
+        "roles": user.roles,
+    })))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(RequireRole(Role::Admin))
+            .route("/users/{id}/roles", web::put().to(set_user_roles)),
+    );
+}
@@ -0,0 +1,173 @@
+use crate::auth;
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::auth::roles::Role;
+use crate::error::AppError;
+use crate::models::api_key::ApiKey;
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyDto {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<DateTime<Utc>>, // omit for a key that never expires
+}
+
+#[derive(Deserialize)]
+pub struct UpdateApiKeyDto {
+    pub name: Option<String>,
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// Only admins may hand the `admin` scope to their keys.
+fn check_scopes(auth_user: &AuthenticatedUser, scopes: &[Scope]) -> Result<(), AppError> {
+    if scopes.contains(&Scope::Admin) && !auth_user.has_role(Role::Admin) {
+        return Err(AppError::forbidden("Only admins can grant the admin scope"));
+    }
+    Ok(())
+}
+
+fn scope_names(scopes: &[Scope]) -> Vec<String> {
+    scopes.iter().map(|s| s.as_str().to_string()).collect()
+}
+
+/// Create a key. The full key is only returned in this response.
+pub async fn create_api_key(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+    form: web::Json<CreateApiKeyDto>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+    check_scopes(&auth_user, &form.scopes)?;
+
+    if form.name.trim().is_empty() {
+        return Err(AppError::bad_request("Name must not be empty"));
+    }
+    if form.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(AppError::bad_request("Expiry must be in the future"));
+    }
+
+    let (prefix, key) = auth::api_key::generate_key();
+
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"
+        INSERT INTO api_keys (id, user_id, name, prefix, secret_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, prefix, scopes, expires_at, last_used_at,
+          created_at as "created_at!: chrono::DateTime<Utc>"
+        "#,
+        Uuid::new_v4(),
+        auth_user.id,
+        form.name.trim(),
+        prefix,
+        auth::token::hash_token(&key),
+        &scope_names(&form.scopes),
+        form.expires_at
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "key": key,
+        "api_key": api_key,
+    })))
+}
+
+/// List the keys of the authenticated user.
+pub async fn list_api_keys(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let keys = sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT id, name, prefix, scopes, expires_at, last_used_at,
+          created_at as "created_at!: chrono::DateTime<Utc>"
+        FROM api_keys
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        auth_user.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// Rename a key or change its scopes.
+pub async fn update_api_key(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+    key_id: web::Path<Uuid>,
+    form: web::Json<UpdateApiKeyDto>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+    if let Some(scopes) = &form.scopes {
+        check_scopes(&auth_user, scopes)?;
+    }
+
+    let name = form.name.as_deref().map(str::trim);
+    if name.is_some_and(str::is_empty) {
+        return Err(AppError::bad_request("Name must not be empty"));
+    }
+
+    let scopes = form.scopes.as_deref().map(scope_names);
+
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"
+        UPDATE api_keys
+        SET name = COALESCE($3, name), scopes = COALESCE($4, scopes)
+        WHERE id = $1 AND user_id = $2
+        RETURNING id, name, prefix, scopes, expires_at, last_used_at,
+          created_at as "created_at!: chrono::DateTime<Utc>"
+        "#,
+        key_id.into_inner(),
+        auth_user.id,
+        name,
+        scopes.as_deref()
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("API key not found"))?;
+
+    Ok(HttpResponse::Ok().json(api_key))
+}
+
+/// Delete a key; requests using it fail immediately.
+pub async fn delete_api_key(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+    key_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let result = sqlx::query!(
+        "DELETE FROM api_keys WHERE id = $1 AND user_id = $2",
+        key_id.into_inner(),
+        auth_user.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found("API key not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/me/api-keys", web::post().to(create_api_key));
+    cfg.route("/me/api-keys", web::get().to(list_api_keys));
+    cfg.route("/me/api-keys/{id}", web::patch().to(update_api_key));
+    cfg.route("/me/api-keys/{id}", web::delete().to(delete_api_key));
+}
@@ -0,0 +1,398 @@
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::auth::roles::Role;
+use crate::commitment;
+use crate::config::Simulator;
+use crate::error::AppError;
+use crate::handlers::simulator::{Fleets, replay_battle};
+use crate::ruleset::Ruleset;
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::types::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// How a battle ended for the player whose history is listed.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BattleResult {
+    Won,
+    Lost,
+    Draw,
+}
+
+impl BattleResult {
+    fn as_str(self) -> &'static str {
+        match self {
+            BattleResult::Won => "won",
+            BattleResult::Lost => "lost",
+            BattleResult::Draw => "draw",
+        }
+    }
+}
+
+/// Paging and filters for `GET /users/{username}/battles`.
+#[derive(Deserialize)]
+pub struct BattleHistoryQuery {
+    limit: Option<i64>,           // page size, 1 to MAX_PAGE_SIZE
+    offset: Option<i64>,          // battles to skip, newest first
+    opponent: Option<String>,     // only battles against this username
+    result: Option<BattleResult>, // only battles won, lost or drawn
+    since: Option<DateTime<Utc>>, // fought at or after
+    until: Option<DateTime<Utc>>, // fought before
+}
+
+#[derive(Serialize)]
+struct BattleSummary {
+    battle_id: Uuid,
+    opponent: Option<String>, // None once the opponent's account is gone
+    side: String,             // "player_a" (started it) or "player_b"
+    result: String,
+    winner: String,
+    rounds: i32,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateCommitmentDto {
+    commitment: String, // hex SHA-256 of a nonce only the caller knows
+}
+
+/// First half of commit-reveal seeding: store the caller's commitment with a fresh
+/// secret server nonce and return that nonce's commitment. The caller then starts a
+/// battle with the commitment ID and their nonce; both nonces are revealed with it.
+pub async fn create_commitment(
+    auth_user: AuthenticatedUser,
+    body: web::Json<CreateCommitmentDto>,
+    pool: web::Data<PgPool>,
+    simulator: web::Data<Simulator>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+
+    let client_commitment = body.commitment.to_ascii_lowercase();
+    if !commitment::is_valid_commitment(&client_commitment) {
+        return Err(AppError::bad_request(
+            "commitment must be a hex SHA-256 digest",
+        ));
+    }
+
+    // Commitments that were never used are dropped once the player asks for a new one
+    sqlx::query!(
+        "DELETE FROM battle_commitments WHERE user_id = $1 AND expires_at <= now()",
+        auth_user.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    let commitment_id = Uuid::new_v4();
+    let server_nonce = commitment::server_nonce();
+    let expires_at = Utc::now() + Duration::minutes(simulator.commitment_minutes as i64);
+
+    sqlx::query!(
+        "INSERT INTO battle_commitments (id, user_id, client_commitment, server_nonce, expires_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        commitment_id,
+        auth_user.id,
+        client_commitment,
+        server_nonce,
+        expires_at
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "commitment_id": commitment_id,
+        "server_commitment": commitment::commit(&server_nonce),
+        "expires_at": expires_at,
+    })))
+}
+
+/// Battles may be looked at by their two players and by admins.
+fn ensure_participant(
+    auth_user: &AuthenticatedUser,
+    player_a: Option<Uuid>,
+    player_b: Option<Uuid>,
+) -> Result<(), AppError> {
+    let participant = [player_a, player_b].contains(&Some(auth_user.id));
+    if participant || auth_user.has_role(Role::Admin) {
+        Ok(())
+    } else {
+        Err(AppError::forbidden("Not a participant of this battle"))
+    }
+}
+
+/// A stored battle: players, seed, ruleset, fleets before and after, and the result.
+/// Players are `null` once their account is gone; older battles lack ruleset and fleets.
+pub async fn get_battle(
+    auth_user: AuthenticatedUser,
+    battle_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+
+    let battle = sqlx::query!(
+        r#"
+        SELECT b.player_a, b.player_b, ua.username AS "player_a_name?", ub.username AS "player_b_name?",
+          b.seed, b.ruleset_name, b.ruleset_version, b.simulator_version, b.fleets_before, b.fleets_after,
+          b.winner, b.rounds, b.created_at, b.client_commitment, b.client_nonce,
+          b.server_commitment, b.server_nonce
+        FROM battles b
+        LEFT JOIN users ua ON ua.id = b.player_a
+        LEFT JOIN users ub ON ub.id = b.player_b
+        WHERE b.id = $1
+        "#,
+        *battle_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("Battle not found"))?;
+
+    ensure_participant(&auth_user, battle.player_a, battle.player_b)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "battle_id": *battle_id,
+        "player_a": battle.player_a_name,
+        "player_b": battle.player_b_name,
+        "seed": battle.seed as u64,
+        "ruleset": battle.ruleset_name.map(|name| serde_json::json!({
+            "name": name,
+            "version": battle.ruleset_version,
+        })),
+        "simulator_version": battle.simulator_version,
+        "fleets_before": battle.fleets_before,
+        "fleets_after": battle.fleets_after,
+        "winner": battle.winner,
+        "rounds": battle.rounds,
+        "created_at": battle.created_at,
+        "seed_commitments": battle.server_commitment.map(|server_commitment| serde_json::json!({
+            "client_commitment": battle.client_commitment,
+            "client_nonce": battle.client_nonce,
+            "server_commitment": server_commitment,
+            "server_nonce": battle.server_nonce,
+        })),
+    })))
+}
+
+/// Round-by-round log of a stored battle. Only its two players and admins may see it.
+pub async fn get_battle_log(
+    auth_user: AuthenticatedUser,
+    battle_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+
+    let battle = sqlx::query!(
+        "SELECT player_a, player_b, rounds, combat_log FROM battles WHERE id = $1",
+        *battle_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("Battle not found"))?;
+
+    ensure_participant(&auth_user, battle.player_a, battle.player_b)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "battle_id": *battle_id,
+        "rounds": battle.rounds,
+        "log": battle.combat_log,
+    })))
+}
+
+/// Fight a stored battle again from its seed, fleets, ruleset version and simulator
+/// version, and check that it ends exactly as stored: same winner, rounds, surviving
+/// fleets and combat log. Open to the battle's players and admins.
+pub async fn verify_battle(
+    auth_user: AuthenticatedUser,
+    battle_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+
+    let battle = sqlx::query!(
+        "SELECT player_a, player_b, seed, ruleset_name, ruleset_version, simulator_version,
+           max_rounds, fleets_before, fleets_after, winner, rounds, combat_log,
+           client_commitment, client_nonce, server_commitment, server_nonce
+         FROM battles
+         WHERE id = $1",
+        *battle_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("Battle not found"))?;
+
+    ensure_participant(&auth_user, battle.player_a, battle.player_b)?;
+
+    let (
+        Some(ruleset_name),
+        Some(ruleset_version),
+        Some(simulator_version),
+        Some(max_rounds),
+        Some(fleets_before),
+    ) = (
+        battle.ruleset_name,
+        battle.ruleset_version,
+        battle.simulator_version,
+        battle.max_rounds,
+        battle.fleets_before,
+    )
+    else {
+        return Err(AppError::conflict(
+            "Battle was recorded before replays were possible",
+        ));
+    };
+
+    let ruleset = Ruleset::stored(&pool, &ruleset_name, ruleset_version, max_rounds as u32)
+        .await?
+        .ok_or_else(|| {
+            AppError::conflict(format!(
+                "Ruleset {} version {} is not stored",
+                ruleset_name, ruleset_version
+            ))
+        })?;
+    let fleets: Fleets = serde_json::from_value(fleets_before).map_err(AppError::internal)?;
+
+    let replay = replay_battle(simulator_version, fleets, battle.seed as u64, &ruleset)
+        .ok_or_else(|| {
+            AppError::conflict(format!(
+                "Simulator version {} is no longer available",
+                simulator_version
+            ))
+        })?;
+
+    let fleets_after = serde_json::to_value(replay.fleets_after()).map_err(AppError::internal)?;
+    let combat_log = serde_json::to_value(&replay.log).map_err(AppError::internal)?;
+    // Battles seeded by commit-reveal also prove that the seed came from both nonces
+    let seed_matches = match (&battle.client_nonce, &battle.server_nonce) {
+        (Some(client_nonce), Some(server_nonce)) => {
+            battle.client_commitment.as_deref() == Some(&commitment::commit(client_nonce))
+                && battle.server_commitment.as_deref() == Some(&commitment::commit(server_nonce))
+                && commitment::battle_seed(server_nonce, client_nonce) == battle.seed as u64
+        }
+        _ => true,
+    };
+
+    let mismatches: Vec<&str> = [
+        ("seed", seed_matches),
+        ("winner", replay.winner == battle.winner),
+        ("rounds", replay.rounds as i32 == battle.rounds),
+        (
+            "fleets_after",
+            Some(&fleets_after) == battle.fleets_after.as_ref(),
+        ),
+        ("combat_log", combat_log == battle.combat_log),
+    ]
+    .into_iter()
+    .filter(|(_, matches)| !matches)
+    .map(|(field, _)| field)
+    .collect();
+
+    if !mismatches.is_empty() {
+        tracing::warn!(battle_id = %battle_id, ?mismatches, "Battle replay does not match");
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "battle_id": *battle_id,
+        "verified": mismatches.is_empty(),
+        "mismatches": mismatches,
+        "seed": battle.seed as u64,
+        "ruleset": { "name": ruleset_name, "version": ruleset_version },
+        "simulator_version": simulator_version,
+    })))
+}
+
+/// A player's battles, newest first, seen from their side. Players see their own
+/// history; admins see anyone's. `next_offset` is set when there are more pages.
+pub async fn list_user_battles(
+    auth_user: AuthenticatedUser,
+    username: web::Path<String>,
+    query: web::Query<BattleHistoryQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(AppError::bad_request("offset must not be negative"));
+    }
+
+    let user_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE lower(username) = lower($1)",
+        *username
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    if user_id != auth_user.id && !auth_user.has_role(Role::Admin) {
+        return Err(AppError::forbidden("Cannot view another player's battles"));
+    }
+
+    // One row past the page tells whether there is another one
+    let mut battles = sqlx::query_as!(
+        BattleSummary,
+        r#"
+        SELECT id AS "battle_id!", opponent AS "opponent?", side AS "side!", result AS "result!",
+          winner AS "winner!", rounds AS "rounds!", created_at AS "created_at!"
+        FROM (
+          SELECT b.id, b.winner, b.rounds, b.created_at,
+            CASE WHEN b.player_a = $1 THEN ub.username ELSE ua.username END AS opponent,
+            CASE WHEN b.player_a = $1 THEN 'player_a' ELSE 'player_b' END AS side,
+            CASE
+              WHEN b.winner = 'Draw' THEN 'draw'
+              WHEN b.winner = CASE WHEN b.player_a = $1 THEN 'Player A' ELSE 'Player B' END THEN 'won'
+              ELSE 'lost'
+            END AS result
+          FROM battles b
+          LEFT JOIN users ua ON ua.id = b.player_a
+          LEFT JOIN users ub ON ub.id = b.player_b
+          WHERE (b.player_a = $1 OR b.player_b = $1)
+            AND ($2::timestamptz IS NULL OR b.created_at >= $2)
+            AND ($3::timestamptz IS NULL OR b.created_at < $3)
+        ) history
+        WHERE ($4::text IS NULL OR lower(opponent) = lower($4))
+          AND ($5::text IS NULL OR result = $5)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $6 OFFSET $7
+        "#,
+        user_id,
+        query.since,
+        query.until,
+        query.opponent,
+        query.result.map(BattleResult::as_str),
+        limit + 1,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let next_offset = (battles.len() as i64 > limit).then_some(offset + limit);
+    battles.truncate(limit as usize);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "battles": battles,
+        "limit": limit,
+        "offset": offset,
+        "next_offset": next_offset,
+    })))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/battles/commitments", web::post().to(create_commitment))
+        .route("/battles/{id}", web::get().to(get_battle))
+        .route("/battles/{id}/log", web::get().to(get_battle_log))
+        .route("/battles/{id}/verify", web::post().to(verify_battle))
+        .route(
+            "/users/{username}/battles",
+            web::get().to(list_user_battles),
+        );
+}
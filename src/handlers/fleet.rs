@@ -1,42 +1,62 @@
-use actix_web::{HttpResponse, Responder, web};
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::error::AppError;
+use crate::validation::ValidationErrors;
+use actix_web::{HttpResponse, web};
 use serde::Deserialize;
 use sqlx::PgPool;
 
 #[derive(Deserialize)]
 pub struct FleetRequest {
-    username: String,
+    username: Option<String>, // defaults to the authenticated player; only admins may name someone else
     ships: i32,
     fighters: i32,
     bombers: i32,
 }
 
-pub async fn create_fleet(pool: web::Data<PgPool>, req: web::Json<FleetRequest>) -> impl Responder {
-    let user = sqlx::query!("SELECT id FROM users WHERE username = $1", req.username)
-        .fetch_one(pool.get_ref())
-        .await;
-
-    match user {
-        Ok(user) => {
-            let result = sqlx::query!(
-                "INSERT INTO fleets (user_id, ships, fighters, bombers) VALUES ($1, $2, $3, $4)",
-                user.id,
-                req.ships,
-                req.fighters,
-                req.bombers
-            )
-            .execute(pool.get_ref())
-            .await;
-
-            match result {
-                Ok(_) => HttpResponse::Ok().body("Fleet created successfully"),
-                Err(e) => {
-                    eprintln!("Error creating fleet: {:?}", e);
-                    HttpResponse::InternalServerError().body("Error creating fleet")
-                }
-            }
+pub async fn create_fleet(
+    auth_user: AuthenticatedUser,
+    pool: web::Data<PgPool>,
+    req: web::Json<FleetRequest>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Fleets)?;
+
+    let mut errors = ValidationErrors::default();
+    for (field, count) in [
+        ("ships", req.ships),
+        ("fighters", req.fighters),
+        ("bombers", req.bombers),
+    ] {
+        if count < 0 {
+            errors.add(field, "Must not be negative");
         }
-        Err(_) => HttpResponse::NotFound().body("User not found"),
     }
+    errors.into_result()?;
+
+    let user_id = match &req.username {
+        None => auth_user.id,
+        Some(username) => sqlx::query_scalar!(
+            "SELECT id FROM users WHERE lower(username) = lower($1)",
+            username
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| auth_user.unknown_player("User not found"))?,
+    };
+
+    auth_user.authorize_for(user_id)?;
+
+    sqlx::query!(
+        "INSERT INTO fleets (user_id, ships, fighters, bombers) VALUES ($1, $2, $3, $4)",
+        user_id,
+        req.ships,
+        req.fighters,
+        req.bombers
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().body("Fleet created successfully"))
 }
 
 pub fn config(cfg: &mut web::ServiceConfig) {
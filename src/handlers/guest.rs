@@ -0,0 +1,150 @@
+use crate::auth::extractor::AuthenticatedUser;
+use crate::auth::jwt::JwtKeys;
+use crate::auth::password::Hasher;
+use crate::config::{PublicUrl, TokenLifetimes};
+use crate::error::AppError;
+use crate::handlers::user::{start_session, user_agent};
+use crate::handlers::{account, verification};
+use crate::mail::Mailer;
+use crate::models::user::User;
+use crate::validation::{self, ValidationErrors};
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct UpgradeGuestDto {
+    pub email: String,
+    pub password: String,
+    pub username: Option<String>, // keeps the generated guest name if omitted
+}
+
+/// Create a guest account without email or password and log it in.
+pub async fn create_guest(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    jwt_keys: web::Data<JwtKeys>,
+    tokens: web::Data<TokenLifetimes>,
+    public_url: web::Data<PublicUrl>,
+) -> Result<HttpResponse, AppError> {
+    let mut suffix = [0u8; 4];
+    OsRng.fill_bytes(&mut suffix);
+    let username = public_url.username(&format!("guest-{}", hex::encode(suffix)));
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        INSERT INTO users (id, username)
+        VALUES ($1, $2)
+        RETURNING
+          id         as "id: Uuid",
+          username   as "username!",
+          email,
+          password,
+          roles      as "roles!",
+          email_verified_at,
+          totp_enabled_at,
+          deletion_scheduled_at,
+          created_at as "created_at!: chrono::DateTime<Utc>"
+        "#,
+        Uuid::new_v4(),
+        username
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    start_session(
+        pool.get_ref(),
+        &req,
+        &jwt_keys,
+        &tokens,
+        user,
+        user_agent(&req),
+    )
+    .await
+}
+
+/// Attach email and password to the authenticated guest. The user ID stays the
+/// same, so fleets, messages and sessions carry over.
+pub async fn upgrade_guest(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    hasher: web::Data<Hasher>,
+    tokens: web::Data<TokenLifetimes>,
+    public_url: web::Data<PublicUrl>,
+    auth_user: AuthenticatedUser,
+    form: web::Json<UpgradeGuestDto>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let mut errors = ValidationErrors::default();
+    if let Some(username) = &form.username {
+        validation::check_username(&mut errors, username);
+    }
+    validation::check_email(&mut errors, &form.email);
+    let mut related = vec![form.email.as_str()];
+    related.extend(form.username.as_deref());
+    validation::check_password(&mut errors, "password", &form.password, &related);
+
+    let username = form
+        .username
+        .as_ref()
+        .map(|username| public_url.username(username));
+    if let Some(username) = &username
+        && account::username_was_deleted(pool.get_ref(), username).await?
+    {
+        errors.add("username", "This name belonged to a deleted account");
+    }
+    errors.into_result()?;
+
+    let hashed = hasher.hash_password(&form.password)?;
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        UPDATE users
+        SET email = $2, password = $3, username = COALESCE($4, username)
+        WHERE id = $1 AND email IS NULL
+        RETURNING
+          id         as "id: Uuid",
+          username   as "username!",
+          email,
+          password,
+          roles      as "roles!",
+          email_verified_at,
+          totp_enabled_at,
+          deletion_scheduled_at,
+          created_at as "created_at!: chrono::DateTime<Utc>"
+        "#,
+        auth_user.id,
+        form.email,
+        hashed,
+        username
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::conflict("Account is not a guest account"))?;
+
+    // Ask the player to confirm the address
+    verification::send_verification_email(pool.get_ref(), mailer, &tokens, user.id, &form.email)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": user.id,
+        "username": user.username,
+        "email": user.email,
+        "roles": user.roles,
+        "guest": false,
+        "email_verified": false,
+        "created_at": user.created_at
+    })))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/guest", web::post().to(create_guest));
+    cfg.route("/guest/upgrade", web::post().to(upgrade_guest));
+}
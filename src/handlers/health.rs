@@ -0,0 +1,85 @@
+use crate::config::Features;
+use actix_web::{HttpResponse, Responder, web};
+use serde_json::json;
+use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+use std::time::Duration;
+
+/// The migrations this build was compiled against.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// A probe that takes longer than this counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Postgres error code for a missing table.
+const UNDEFINED_TABLE: &str = "42P01";
+
+/// Liveness: the process is up and serving requests. Never touches the database.
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Readiness: Postgres answers and its schema is at least at the version this build
+/// expects. A newer schema is fine, so instances still on the previous build keep
+/// serving while a rolling deploy migrates. 503 otherwise, so load balancers stop
+/// routing to this instance.
+pub async fn readyz(pool: web::Data<PgPool>) -> impl Responder {
+    let expected = MIGRATOR.iter().map(|m| m.version).max();
+
+    // Not a `query!` macro: `_sqlx_migrations` is created by `sqlx migrate run`,
+    // not by the migrations themselves
+    let applied = actix_web::rt::time::timeout(
+        CHECK_TIMEOUT,
+        sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT max(version) FROM _sqlx_migrations WHERE success",
+        )
+        .fetch_one(pool.get_ref()),
+    )
+    .await;
+
+    let (database, applied) = match applied {
+        Ok(Ok(applied)) => ("ok", applied),
+        // Reachable, but `sqlx migrate run` never ran
+        Ok(Err(sqlx::Error::Database(e))) if e.code().as_deref() == Some(UNDEFINED_TABLE) => {
+            ("ok", None)
+        }
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Readiness check failed");
+            ("error", None)
+        }
+        Err(_) => ("timeout", None),
+    };
+
+    let ready = database == "ok" && applied >= expected;
+    let body = json!({
+        "status": if ready { "ready" } else { "unavailable" },
+        "checks": {
+            "database": database,
+            "migrations": {
+                "expected": expected,
+                "applied": applied,
+            },
+        },
+    });
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Build information: crate version, git commit and enabled features.
+pub async fn version(features: web::Data<Features>) -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("GIT_HASH"),
+        "features": features.get_ref(),
+    }))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz));
+    cfg.route("/readyz", web::get().to(readyz));
+    cfg.route("/version", web::get().to(version));
+}
@@ -0,0 +1,8 @@
+use crate::auth::jwt::JwtKeys;
+use actix_web::{HttpResponse, Responder, web};
+
+/// Publish the public keys other services use to verify our access tokens.
+/// Empty when tokens are signed with a shared HS256 secret.
+pub async fn jwks(keys: web::Data<JwtKeys>) -> impl Responder {
+    HttpResponse::Ok().json(keys.jwks())
+}
@@ -0,0 +1,20 @@
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use actix_web::{HttpResponse, web};
+use sqlx::PgPool;
+
+/// Prometheus scrape endpoint.
+pub async fn metrics(
+    metrics: web::Data<Metrics>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let body = metrics.render(pool.get_ref()).map_err(AppError::internal)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+}
@@ -1,7 +1,18 @@
+pub mod account;
 pub mod activity_pub;
+pub mod admin;
+pub mod api_keys;
+pub mod battles;
 pub mod fleet;
+pub mod guest;
+pub mod health;
+pub mod jwks;
+pub mod metrics;
+pub mod password;
 pub mod simulator;
 pub mod sse;
+pub mod two_factor;
 pub mod user;
+pub mod verification;
 pub mod webfinger;
 pub mod websocket;
@@ -0,0 +1,203 @@
+use crate::auth;
+use crate::auth::password::Hasher;
+use crate::auth::throttle::{self, FailedLogin, ThrottleKey};
+use crate::client_ip;
+use crate::config::TokenLifetimes;
+use crate::error::AppError;
+use crate::handlers::user;
+use crate::mail::{self, Email, Mailer};
+use crate::validation::{self, ValidationErrors};
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordDto {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordDto {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Email a single-use reset token valid for `tokens.password_reset_minutes`.
+///
+/// Always answers 202 once the email is looked up; the token is stored and mailed in the
+/// background, so neither the answer nor its timing shows which emails are registered.
+/// Every request counts against the client IP's login throttle, so the endpoint can't
+/// be used to flood inboxes.
+pub async fn forgot_password(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    mailer: web::Data<dyn Mailer>,
+    tokens: web::Data<TokenLifetimes>,
+    form: web::Json<ForgotPasswordDto>,
+) -> Result<HttpResponse, AppError> {
+    let ip = client_ip::client_ip(&req);
+    let throttle_keys: Vec<ThrottleKey> = ip.iter().cloned().map(ThrottleKey::Ip).collect();
+    user::ensure_not_locked(
+        pool.get_ref(),
+        &throttle_keys,
+        None,
+        &form.email,
+        ip.as_deref(),
+    )
+    .await?;
+
+    let user = sqlx::query!(
+        r#"SELECT id, email as "email!" FROM users WHERE lower(email) = lower($1)"#,
+        form.email
+    )
+    .fetch_optional(pool.get_ref())
+    .await?;
+
+    let attempt = FailedLogin {
+        user_id: user.as_ref().map(|user| user.id),
+        email: &form.email,
+        ip: ip.as_deref(),
+        reason: "password_reset",
+    };
+    throttle::record_failure(pool.get_ref(), &throttle_keys, attempt).await?;
+
+    if let Some(user) = user {
+        let pool = pool.get_ref().clone();
+        let tokens = **tokens;
+        actix_web::rt::spawn(
+            async move {
+                if let Err(e) = send_reset_token(&pool, mailer, &tokens, user.id, user.email).await
+                {
+                    tracing::error!(user_id = %user.id, error = %e, "Cannot create password reset token");
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "If the email is registered, a reset link has been sent"
+    })))
+}
+
+/// Store a new reset token for `user_id`, replacing any unused one, and mail it.
+async fn send_reset_token(
+    pool: &PgPool,
+    mailer: web::Data<dyn Mailer>,
+    tokens: &TokenLifetimes,
+    user_id: Uuid,
+    to: String,
+) -> Result<(), sqlx::Error> {
+    let token = auth::token::generate_token();
+    let expires_at = Utc::now() + Duration::minutes(tokens.password_reset_minutes.into());
+
+    let mut tx = pool.begin().await?;
+
+    // Only the most recent link works
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (id, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        auth::token::hash_token(&token),
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let email = Email {
+        to,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Someone asked to reset the password of your account.\n\n\
+             Reset token: {}\n\n\
+             POST it to /password/reset with your new password within the next {} minutes. \
+             If this wasn't you, you can ignore this email.",
+            token, tokens.password_reset_minutes
+        ),
+    };
+
+    mail::deliver(mailer, email).await;
+
+    Ok(())
+}
+
+/// Redeem a reset token: set the new password and sign the user out everywhere.
+pub async fn reset_password(
+    pool: web::Data<PgPool>,
+    hasher: web::Data<Hasher>,
+    form: web::Json<ResetPasswordDto>,
+) -> Result<HttpResponse, AppError> {
+    let mut errors = ValidationErrors::default();
+    validation::check_password(&mut errors, "new_password", &form.new_password, &[]);
+    errors.into_result()?;
+
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query!(
+        r#"
+        SELECT id, user_id, expires_at, used_at
+        FROM password_reset_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        auth::token::hash_token(&form.token)
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let row = match row {
+        Some(row) if row.used_at.is_none() && row.expires_at > Utc::now() => row,
+        _ => {
+            return Err(AppError::bad_request("Invalid or expired reset token"));
+        }
+    };
+
+    let hashed = hasher.hash_password(&form.new_password)?;
+
+    sqlx::query!(
+        "UPDATE users SET password = $1 WHERE id = $2",
+        hashed,
+        row.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE password_reset_tokens SET used_at = now() WHERE id = $1",
+        row.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Whoever knew the old password may hold a session; end all of them
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+        row.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/password/forgot", web::post().to(forgot_password));
+    cfg.route("/password/reset", web::post().to(reset_password));
+}
@@ -1,11 +1,22 @@
-use actix_web::{HttpResponse, Responder, web};
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::commitment;
+use crate::config::EmailVerification;
+use crate::error::AppError;
+use crate::handlers::activity_pub::receive_battle_request;
+use crate::handlers::verification::ensure_verified_for_battle;
+use crate::metrics::Metrics;
+use crate::ruleset::{Ruleset, UnitType};
+use actix_web::{HttpResponse, web};
+use chrono::Utc;
 use rand::Rng;
 use rand::SeedableRng;
 use rand_pcg::Pcg64;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 use sqlx::types::Uuid;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BattleRequestActivity {
@@ -14,16 +25,8 @@ pub struct BattleRequestActivity {
     pub actor: Uuid,
     pub target: Uuid,
     pub fleet: Fleet,
-    pub seed: u64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BattleResultActivity {
-    #[serde(rename = "type")]
-    pub activity_type: String,
-    pub actor: Uuid,
-    pub target: Uuid,
-    pub result: BattleOutcome,
+    pub commitment_id: Uuid, // from `POST /battles/commitments`
+    pub nonce: String,       // the nonce behind that commitment
 }
 
 #[derive(Default, Debug, Deserialize, Serialize, Clone, sqlx::FromRow)]
@@ -33,338 +36,636 @@ pub struct Fleet {
     bombers: Option<i32>,
 }
 
+impl Fleet {
+    fn column(&mut self, unit: UnitType) -> &mut Option<i32> {
+        match unit {
+            UnitType::Ships => &mut self.ships,
+            UnitType::Fighters => &mut self.fighters,
+            UnitType::Bombers => &mut self.bombers,
+        }
+    }
+
+    /// Units of one type; unset and negative counts are none.
+    pub fn count(&self, unit: UnitType) -> i64 {
+        let count = match unit {
+            UnitType::Ships => self.ships,
+            UnitType::Fighters => self.fighters,
+            UnitType::Bombers => self.bombers,
+        };
+        count.unwrap_or(0).max(0) as i64
+    }
+
+    /// Update a type the fleet has; unset types stay unset.
+    fn set_count(&mut self, unit: UnitType, count: i64) {
+        if let Some(column) = self.column(unit).as_mut() {
+            *column = count as i32;
+        }
+    }
+}
+
+/// Version of the battle math below, stored with every battle. Bump it for any change
+/// that could alter the outcome of a stored battle (including a `rand` upgrade that
+/// changes `gen_range`), and keep the old math reachable from `replay_battle`.
+pub const SIMULATOR_VERSION: i32 = 1;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct BattleOutcome {
-    winner: String,
+    pub(crate) winner: String,
     player_a_remaining: Fleet,
     player_b_remaining: Fleet,
+    #[serde(skip)]
+    pub(crate) rounds: u32,
+    #[serde(skip)]
+    pub(crate) log: Vec<RoundLog>,
+}
+
+impl BattleOutcome {
+    /// Both fleets as they came out of the battle.
+    pub(crate) fn fleets_after(&self) -> Fleets {
+        Fleets {
+            player_a: self.player_a_remaining.clone(),
+            player_b: self.player_b_remaining.clone(),
+        }
+    }
+}
+
+/// Both players' fleets, as stored in `battles.fleets_before` and `fleets_after`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Fleets {
+    pub player_a: Fleet,
+    pub player_b: Fleet,
+}
+
+/// What happened in one round of a battle.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RoundLog {
+    round: u32,
+    player_a: SideLog,
+    player_b: SideLog,
+}
+
+/// One side's part of a round, by unit type: the fire roll in percent (the RNG
+/// draws), the damage dealt to each enemy type before shields, and the units lost.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SideLog {
+    rolls: BTreeMap<UnitType, i64>,
+    damage_dealt: BTreeMap<UnitType, i64>,
+    units_lost: BTreeMap<UnitType, i64>,
 }
 
 #[derive(Deserialize)]
 pub struct BattleRequest {
-    player_a: String, // username of player A
-    player_b: String, // username of player B
-    seed: u64,        // Optional seed for reproducibility
+    player_a: Option<String>, // username of player A; defaults to the caller, admins may set it
+    player_b: String,         // username of player B
+    commitment_id: Uuid,      // the caller's seed commitment, from `POST /battles/commitments`
+    nonce: String,            // the nonce behind that commitment
+}
+
+/// `?log=true` adds the round-by-round combat log to a battle response.
+#[derive(Deserialize)]
+pub struct BattleLogQuery {
+    #[serde(default)]
+    pub log: bool,
 }
 
 #[derive(Serialize)]
 pub struct BattleResponse {
+    battle_id: Uuid,
     winner: String,
     player_a_remaining: Fleet,
     player_b_remaining: Fleet,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log: Option<Vec<RoundLog>>,
 }
 
-pub fn simulate_battle(mut player_a: Fleet, mut player_b: Fleet, seed: u64) -> BattleOutcome {
-    let mut rng = Pcg64::seed_from_u64(seed);
+impl BattleResponse {
+    fn new(battle_id: Uuid, outcome: BattleOutcome, include_log: bool) -> Self {
+        BattleResponse {
+            battle_id,
+            winner: outcome.winner,
+            player_a_remaining: outcome.player_a_remaining,
+            player_b_remaining: outcome.player_b_remaining,
+            log: include_log.then_some(outcome.log),
+        }
+    }
+}
 
-    while (player_a.ships.unwrap_or(0) > 0
-        || player_a.fighters.unwrap_or(0) > 0
-        || player_a.bombers.unwrap_or(0) > 0)
-        && (player_b.ships.unwrap_or(0) > 0
-            || player_b.fighters.unwrap_or(0) > 0
-            || player_b.bombers.unwrap_or(0) > 0)
-    {
-        let damage_to_b = rng.gen_range(1..10);
-        let damage_to_a = rng.gen_range(1..10);
-
-        // Apply damage to Player B
-        let mut remaining_damage = damage_to_b;
-
-        if let Some(b_ships) = player_b.ships {
-            let effective_damage = b_ships.min(remaining_damage);
-            player_b.ships = Some(b_ships - effective_damage);
-            remaining_damage -= effective_damage;
+/// Units of one side during a battle, indexed by `UnitType::index`.
+struct Forces {
+    counts: [i64; 3],
+    wear: [i128; 3], // damage carried by a type's next casualty; always below its hull
+}
+
+impl Forces {
+    fn new(fleet: &Fleet) -> Self {
+        Forces {
+            counts: UnitType::ALL.map(|unit| fleet.count(unit)),
+            wear: [0; 3],
         }
+    }
 
-        if remaining_damage > 0 {
-            if let Some(b_fighters) = player_b.fighters {
-                let effective_damage = b_fighters.min(remaining_damage);
-                player_b.fighters = Some(b_fighters - effective_damage);
-                remaining_damage -= effective_damage;
+    fn total(&self) -> i64 {
+        self.counts.iter().sum()
+    }
+
+    /// Damage this side deals to each of `target`'s unit types in one round, before
+    /// shields: every type's fire times its roll, split over the target's types by
+    /// head count, times the rapid-fire bonus against each.
+    fn fire_at(
+        &self,
+        target: &Forces,
+        ruleset: &Ruleset,
+        rng: &mut Pcg64,
+        log: &mut SideLog,
+    ) -> [i128; 3] {
+        let mut damage = [0i128; 3];
+        let targets = target.total() as i128;
+
+        for attacker in UnitType::ALL {
+            let count = self.counts[attacker.index()];
+            if count == 0 {
+                continue;
+            }
+            let stats = ruleset.unit(attacker);
+            let roll = rng.gen_range(ruleset.roll.min_percent..=ruleset.roll.max_percent);
+            log.rolls.insert(attacker, roll);
+            let firepower = count as i128 * stats.attack as i128 * roll as i128 / 100;
+
+            for defender in UnitType::ALL {
+                let share = firepower * target.counts[defender.index()] as i128 / targets;
+                damage[defender.index()] += share * stats.rapid_fire_against(defender) as i128;
             }
         }
 
-        if remaining_damage > 0 {
-            if let Some(b_bombers) = player_b.bombers {
-                let effective_damage = b_bombers.min(remaining_damage);
-                player_b.bombers = Some(b_bombers - effective_damage);
+        for defender in UnitType::ALL {
+            if target.counts[defender.index()] > 0 {
+                let dealt = i64::try_from(damage[defender.index()]).unwrap_or(i64::MAX);
+                log.damage_dealt.insert(defender, dealt);
             }
         }
 
-        // Apply damage to Player A
-        let mut remaining_damage = damage_to_a;
-
-        if let Some(a_ships) = player_a.ships {
-            let effective_damage = a_ships.min(remaining_damage);
-            player_a.ships = Some(a_ships - effective_damage);
-            remaining_damage -= effective_damage;
-        }
+        damage
+    }
 
-        if remaining_damage > 0 {
-            if let Some(a_fighters) = player_a.fighters {
-                let effective_damage = a_fighters.min(remaining_damage);
-                player_a.fighters = Some(a_fighters - effective_damage);
-                remaining_damage -= effective_damage;
+    /// Apply one round of incoming damage. Shields soak up to `shields` per unit;
+    /// the rest destroys whole units, and what is left over wears the next one.
+    fn take(&mut self, damage: [i128; 3], ruleset: &Ruleset, log: &mut SideLog) {
+        for unit in UnitType::ALL {
+            let i = unit.index();
+            if self.counts[i] == 0 {
+                continue;
             }
+            let stats = ruleset.unit(unit);
+            let soaked = stats.shields as i128 * self.counts[i] as i128;
+            let hull_damage = (damage[i] - soaked).max(0) + self.wear[i];
+            let lost = (hull_damage / stats.hull as i128).min(self.counts[i] as i128);
+
+            self.counts[i] -= lost as i64;
+            log.units_lost.insert(unit, lost as i64);
+            self.wear[i] = if self.counts[i] == 0 {
+                0
+            } else {
+                hull_damage - lost * stats.hull as i128
+            };
         }
+    }
 
-        if remaining_damage > 0 {
-            if let Some(a_bombers) = player_a.bombers {
-                let effective_damage = a_bombers.min(remaining_damage);
-                player_a.bombers = Some(a_bombers - effective_damage);
-            }
+    /// `fleet` with the surviving units; types the fleet didn't have stay unset.
+    fn remaining(&self, mut fleet: Fleet) -> Fleet {
+        for unit in UnitType::ALL {
+            fleet.set_count(unit, self.counts[unit.index()]);
         }
+        fleet
+    }
+}
+
+/// Fight until one side has no units left or `ruleset.max_rounds` have passed. Only
+/// integer math, so the same fleets, seed and ruleset always give the same outcome.
+#[tracing::instrument(name = "battle", skip(player_a, player_b, ruleset))]
+pub fn simulate_battle(
+    player_a: Fleet,
+    player_b: Fleet,
+    seed: u64,
+    ruleset: &Ruleset,
+) -> BattleOutcome {
+    let mut rng = Pcg64::seed_from_u64(seed);
+    let mut a = Forces::new(&player_a);
+    let mut b = Forces::new(&player_b);
+    let mut rounds = 0;
+    let mut log = Vec::new();
+
+    while rounds < ruleset.max_rounds && a.total() > 0 && b.total() > 0 {
+        rounds += 1;
+        let mut round = RoundLog {
+            round: rounds,
+            ..Default::default()
+        };
+
+        // Both sides fire at the fleets as they stood at the start of the round
+        let damage_to_b = a.fire_at(&b, ruleset, &mut rng, &mut round.player_a);
+        let damage_to_a = b.fire_at(&a, ruleset, &mut rng, &mut round.player_b);
+        b.take(damage_to_b, ruleset, &mut round.player_b);
+        a.take(damage_to_a, ruleset, &mut round.player_a);
+
+        log.push(round);
     }
 
     // Determine the winner by comparing total remaining units
-    let total_a = player_a.ships.unwrap_or(0)
-        + player_a.fighters.unwrap_or(0)
-        + player_a.bombers.unwrap_or(0);
-    let total_b = player_b.ships.unwrap_or(0)
-        + player_b.fighters.unwrap_or(0)
-        + player_b.bombers.unwrap_or(0);
-
-    let winner = if total_a > total_b {
-        "Player A".to_string()
-    } else if total_b > total_a {
-        "Player B".to_string()
-    } else {
-        "Draw".to_string() // In case of a tie
-    };
+    let winner = match a.total().cmp(&b.total()) {
+        Ordering::Greater => "Player A",
+        Ordering::Less => "Player B",
+        Ordering::Equal => "Draw", // In case of a tie
+    }
+    .to_string();
+    tracing::info!(rounds, %winner, "Battle simulated");
 
     BattleOutcome {
         winner,
-        player_a_remaining: player_a,
-        player_b_remaining: player_b,
+        player_a_remaining: a.remaining(player_a),
+        player_b_remaining: b.remaining(player_b),
+        rounds,
+        log,
     }
 }
 
-pub async fn battle_handler(
-    req: web::Json<BattleRequest>,
-    pool: web::Data<PgPool>,
-) -> impl Responder {
-    // Fetch fleets for both players by joining `fleets` and `users` tables
-    let player_a_fleet = sqlx::query_as!(
-        Fleet,
-        r#"
-        SELECT f.ships, f.fighters, f.bombers 
-        FROM fleets f
-        INNER JOIN users u ON f.user_id = u.id
-        WHERE u.username = $1
-        "#,
-        req.player_a
-    )
-    .fetch_one(pool.get_ref())
-    .await
-    .expect("Player A fleet not found");
-
-    let player_b_fleet = sqlx::query_as!(
-        Fleet,
-        r#"
-        SELECT f.ships, f.fighters, f.bombers 
-        FROM fleets f
-        INNER JOIN users u ON f.user_id = u.id
-        WHERE u.username = $1
-        "#,
-        req.player_b
-    )
-    .fetch_one(pool.get_ref())
-    .await
-    .expect("Player B fleet not found");
-
-    // Simulate the battle
-    let outcome = simulate_battle(player_a_fleet, player_b_fleet, req.seed);
-
-    // Update fleets for Player A
-    sqlx::query!(
-        r#"
-        UPDATE fleets
-        SET ships = $1, fighters = $2, bombers = $3
-        WHERE user_id = (SELECT id FROM users WHERE username = $4)
-        "#,
-        outcome.player_a_remaining.ships,
-        outcome.player_a_remaining.fighters,
-        outcome.player_a_remaining.bombers,
-        req.player_a
-    )
-    .execute(pool.get_ref())
-    .await
-    .expect("Failed to update Player A's fleet");
-
-    // Update fleets for Player B
-    sqlx::query!(
-        r#"
-        UPDATE fleets
-        SET ships = $1, fighters = $2, bombers = $3
-        WHERE user_id = (SELECT id FROM users WHERE username = $4)
-        "#,
-        outcome.player_b_remaining.ships,
-        outcome.player_b_remaining.fighters,
-        outcome.player_b_remaining.bombers,
-        req.player_b
-    )
-    .execute(pool.get_ref())
-    .await
-    .expect("Failed to update Player B's fleet");
-
-    let response = BattleResponse {
-        winner: outcome.winner,
-        player_a_remaining: outcome.player_a_remaining,
-        player_b_remaining: outcome.player_b_remaining,
-    };
-
-    HttpResponse::Ok().json(response)
+/// Fight a stored battle again with the simulator version it was fought with; `None`
+/// if this build no longer has that version.
+pub fn replay_battle(
+    simulator_version: i32,
+    fleets: Fleets,
+    seed: u64,
+    ruleset: &Ruleset,
+) -> Option<BattleOutcome> {
+    match simulator_version {
+        SIMULATOR_VERSION => Some(simulate_battle(
+            fleets.player_a,
+            fleets.player_b,
+            seed,
+            ruleset,
+        )),
+        _ => None,
+    }
 }
 
-pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.route("/simulate_battle", web::post().to(battle_handler));
+/// Two players about to fight, with the fleets they bring.
+struct Matchup {
+    player_a: Uuid,
+    player_b: Uuid,
+    fleet_a: Fleet,
+    fleet_b: Fleet,
+    seed: RevealedSeed,
 }
 
-pub async fn handle_battle_request(
-    activity: web::Json<BattleRequestActivity>,
-    pool: web::Data<PgPool>,
-) -> Result<HttpResponse, actix_web::Error> {
-    // Fetch actor's fleet
-    let actor_fleet = sqlx::query_as!(
-        Fleet,
-        "SELECT ships, fighters, bombers 
-         FROM fleets 
-         WHERE user_id = $1",
-        activity.actor // Use actor UUID directly
-    )
-    .fetch_one(pool.get_ref())
-    .await;
+/// A seed commitment opened for a battle; stored with it so the seed can be audited.
+struct RevealedSeed {
+    client_commitment: String,
+    client_nonce: String,
+    server_commitment: String,
+    server_nonce: String,
+    seed: u64,
+}
 
-    if actor_fleet.is_err() {
-        return Ok(HttpResponse::BadRequest().body("Actor fleet not found"));
+/// Use up `user_id`'s commitment `commitment_id`, check `client_nonce` against it and
+/// derive the battle seed. The commitment is only gone once `tx` commits.
+#[tracing::instrument(target = "db", level = "debug", skip_all, fields(%commitment_id, %user_id))]
+async fn reveal_seed(
+    tx: &mut PgConnection,
+    commitment_id: Uuid,
+    user_id: Uuid,
+    client_nonce: &str,
+) -> Result<RevealedSeed, AppError> {
+    if !commitment::is_valid_nonce(client_nonce) {
+        return Err(AppError::bad_request(
+            "nonce must be 16 to 128 printable characters without spaces",
+        ));
     }
-    let actor_fleet = actor_fleet.unwrap();
-
-    // Fetch target's fleet
-    let target_fleet = sqlx::query_as!(
-        Fleet,
-        "SELECT ships, fighters, bombers 
-         FROM fleets 
-         WHERE user_id = $1",
-        activity.target // Use target UUID directly
+
+    let stored = sqlx::query!(
+        "DELETE FROM battle_commitments
+         WHERE id = $1 AND user_id = $2
+         RETURNING client_commitment, server_nonce, expires_at",
+        commitment_id,
+        user_id
     )
-    .fetch_one(pool.get_ref())
-    .await;
+    .fetch_optional(tx)
+    .await?
+    .ok_or_else(|| AppError::bad_request("Unknown or already used battle commitment"))?;
 
-    if target_fleet.is_err() {
-        return Ok(HttpResponse::BadRequest().body("Target fleet not found"));
+    if stored.expires_at <= Utc::now() {
+        return Err(AppError::bad_request("Battle commitment has expired"));
+    }
+    if commitment::commit(client_nonce) != stored.client_commitment {
+        return Err(AppError::bad_request("Nonce does not match the commitment"));
     }
-    let target_fleet = target_fleet.unwrap();
 
-    // Simulate the battle
-    let outcome = simulate_battle(actor_fleet.clone(), target_fleet.clone(), activity.seed);
+    Ok(RevealedSeed {
+        seed: commitment::battle_seed(&stored.server_nonce, client_nonce),
+        server_commitment: commitment::commit(&stored.server_nonce),
+        client_commitment: stored.client_commitment,
+        client_nonce: client_nonce.to_string(),
+        server_nonce: stored.server_nonce,
+    })
+}
+
+/// Lock both players' fleets until `tx` ends, so nothing changes them mid-battle. Rows
+/// are locked in `user_id` order; two battles between the same players can't deadlock.
+#[tracing::instrument(target = "db", level = "debug", skip_all, fields(%player_a, %player_b))]
+async fn lock_fleets(
+    tx: &mut PgConnection,
+    player_a: Uuid,
+    player_b: Uuid,
+) -> Result<(Option<Fleet>, Option<Fleet>), sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT user_id, ships, fighters, bombers
+         FROM fleets
+         WHERE user_id = ANY($1)
+         ORDER BY user_id, id
+         FOR UPDATE",
+        &[player_a, player_b][..]
+    )
+    .fetch_all(tx)
+    .await?;
+
+    let fleet_of = |user_id: Uuid| {
+        rows.iter()
+            .find(|row| row.user_id == user_id)
+            .map(|row| Fleet {
+                ships: row.ships,
+                fighters: row.fighters,
+                bombers: row.bombers,
+            })
+    };
+    Ok((fleet_of(player_a), fleet_of(player_b)))
+}
 
-    // Update actor's fleet
+#[tracing::instrument(target = "db", level = "debug", skip_all, fields(%user_id))]
+async fn update_fleet(
+    tx: &mut PgConnection,
+    user_id: Uuid,
+    fleet: &Fleet,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         "UPDATE fleets 
          SET ships = $1, fighters = $2, bombers = $3 
          WHERE user_id = $4",
-        outcome.player_a_remaining.ships,
-        outcome.player_a_remaining.fighters,
-        outcome.player_a_remaining.bombers,
-        activity.actor // Use actor UUID directly
+        fleet.ships,
+        fleet.fighters,
+        fleet.bombers,
+        user_id
     )
-    .execute(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Failed to update actor fleet: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Failed to update actor fleet")
-    })?;
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+/// Simulate the battle, save the surviving fleets and store the battle, then commit.
+/// Fleets and history change together or not at all. Returns the battle's ID.
+async fn fight(
+    mut tx: Transaction<'_, Postgres>,
+    matchup: Matchup,
+    ruleset: &Ruleset,
+    metrics: &Metrics,
+) -> Result<(Uuid, BattleOutcome), AppError> {
+    let fleets_before = serde_json::to_value(Fleets {
+        player_a: matchup.fleet_a.clone(),
+        player_b: matchup.fleet_b.clone(),
+    })
+    .map_err(AppError::internal)?;
+
+    let seed = matchup.seed;
+    let outcome = simulate_battle(matchup.fleet_a, matchup.fleet_b, seed.seed, ruleset);
+    update_fleet(&mut tx, matchup.player_a, &outcome.player_a_remaining).await?;
+    update_fleet(&mut tx, matchup.player_b, &outcome.player_b_remaining).await?;
+
+    let battle_id = Uuid::new_v4();
+    let fleets_after = serde_json::to_value(outcome.fleets_after()).map_err(AppError::internal)?;
+    let combat_log = serde_json::to_value(&outcome.log).map_err(AppError::internal)?;
 
-    // Update target's fleet
     sqlx::query!(
-        "UPDATE fleets 
-         SET ships = $1, fighters = $2, bombers = $3 
-         WHERE user_id = $4",
-        outcome.player_b_remaining.ships,
-        outcome.player_b_remaining.fighters,
-        outcome.player_b_remaining.bombers,
-        activity.target // Use target UUID directly
+        "INSERT INTO battles (id, player_a, player_b, seed, ruleset_name, ruleset_version,
+           simulator_version, max_rounds, fleets_before, fleets_after, winner, rounds, combat_log,
+           client_commitment, client_nonce, server_commitment, server_nonce)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
+        battle_id,
+        matchup.player_a,
+        matchup.player_b,
+        seed.seed as i64, // same bits; read back with `as u64`
+        ruleset.name,
+        ruleset.version as i32,
+        SIMULATOR_VERSION,
+        ruleset.max_rounds as i32,
+        fleets_before,
+        fleets_after,
+        outcome.winner,
+        outcome.rounds as i32,
+        combat_log,
+        seed.client_commitment,
+        seed.client_nonce,
+        seed.server_commitment,
+        seed.server_nonce
     )
-    .execute(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Failed to update target fleet: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Failed to update target fleet")
-    })?;
+    .execute(&mut *tx)
+    .await?;
 
-    // Return battle result
-    Ok(HttpResponse::Ok().json(outcome))
+    tx.commit().await?;
+    metrics.record_battle(outcome.rounds);
+
+    Ok((battle_id, outcome))
 }
 
-async fn _update_fleet(username: String, updated_fleet: Fleet, pool: web::Data<PgPool>) {
-    sqlx::query!(
-        "UPDATE fleets SET ships = $1, fighters = $2, bombers = $3 WHERE user_id = (SELECT id FROM users WHERE username = $4)",
-        updated_fleet.ships,
-        updated_fleet.fighters,
-        updated_fleet.bombers,
-        username
+pub async fn battle_handler(
+    auth_user: AuthenticatedUser,
+    req: web::Json<BattleRequest>,
+    pool: web::Data<PgPool>,
+    email_verification: web::Data<EmailVerification>,
+    ruleset: web::Data<Ruleset>,
+    metrics: web::Data<Metrics>,
+    query: web::Query<BattleLogQuery>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+
+    // Player A is the caller unless an admin starts the battle on someone's behalf
+    let player_a_id = match &req.player_a {
+        None => auth_user.id,
+        Some(username) => sqlx::query_scalar!(
+            "SELECT id FROM users WHERE lower(username) = lower($1)",
+            username
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| auth_user.unknown_player("Player A not found"))?,
+    };
+
+    auth_user.authorize_for(player_a_id)?;
+
+    let player_b_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE lower(username) = lower($1)",
+        req.player_b
     )
-    .execute(pool.get_ref())
-    .await
-    .expect("Failed to update fleet");
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("Player B not found"))?;
+
+    ensure_verified_for_battle(&pool, **email_verification, &[player_a_id, player_b_id]).await?;
+
+    // The caller's commitment seeds the battle; then fetch fleets for both players
+    let mut tx = pool.begin().await?;
+    let seed = reveal_seed(&mut tx, req.commitment_id, auth_user.id, &req.nonce).await?;
+    let (player_a_fleet, player_b_fleet) = lock_fleets(&mut tx, player_a_id, player_b_id).await?;
+    let matchup = Matchup {
+        player_a: player_a_id,
+        player_b: player_b_id,
+        fleet_a: player_a_fleet.ok_or_else(|| AppError::not_found("Player A has no fleet"))?,
+        fleet_b: player_b_fleet.ok_or_else(|| AppError::not_found("Player B has no fleet"))?,
+        seed,
+    };
+
+    let (battle_id, outcome) = fight(tx, matchup, &ruleset, &metrics).await?;
+
+    Ok(HttpResponse::Ok().json(BattleResponse::new(battle_id, outcome, query.log)))
 }
 
-pub async fn send_battle_request(
-    activity: BattleRequestActivity,
-    target_inbox: &str,
-) -> Result<(), reqwest::Error> {
-    let client = Client::new();
-    client
-        .post(target_inbox)
-        .header("Content-Type", "application/activity+json")
-        .json(&activity)
-        .send()
-        .await?;
-    Ok(())
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/simulate_battle", web::post().to(battle_handler));
 }
 
+pub async fn handle_battle_request(
+    activity: web::Json<BattleRequestActivity>,
+    pool: web::Data<PgPool>,
+    email_verification: web::Data<EmailVerification>,
+    ruleset: web::Data<Ruleset>,
+    metrics: web::Data<Metrics>,
+    committed_by: Uuid,
+    include_log: bool,
+) -> Result<HttpResponse, AppError> {
+    ensure_verified_for_battle(
+        &pool,
+        **email_verification,
+        &[activity.actor, activity.target],
+    )
+    .await?;
+
+    // The sender's commitment seeds the battle; then fetch the actor's and target's fleets
+    let mut tx = pool.begin().await?;
+    let seed = reveal_seed(
+        &mut tx,
+        activity.commitment_id,
+        committed_by,
+        &activity.nonce,
+    )
+    .await?;
+    let (actor_fleet, target_fleet) = lock_fleets(&mut tx, activity.actor, activity.target).await?;
+    let matchup = Matchup {
+        player_a: activity.actor,
+        player_b: activity.target,
+        fleet_a: actor_fleet.ok_or_else(|| AppError::bad_request("Actor fleet not found"))?,
+        fleet_b: target_fleet.ok_or_else(|| AppError::bad_request("Target fleet not found"))?,
+        seed,
+    };
+
+    let (battle_id, outcome) = fight(tx, matchup, &ruleset, &metrics).await?;
+
+    // Return battle result
+    Ok(HttpResponse::Ok().json(BattleResponse::new(battle_id, outcome, include_log)))
+}
+
+/// Send a battle request from the caller to `target`'s inbox. Every player lives on
+/// this server, so it is delivered in-process and answered with the battle result.
+#[allow(clippy::too_many_arguments)] // one per extractor
 pub async fn send_battle_request_handler(
+    auth_user: AuthenticatedUser,
     activity: web::Json<BattleRequestActivity>,
     pool: web::Data<PgPool>,
-) -> impl Responder {
-    // Fetch the actor's fleet from the database
-    let actor_fleet = sqlx::query_as!(
-        Fleet,
-        "SELECT ships, fighters, bombers 
-         FROM fleets 
-         WHERE user_id = $1",
-        activity.actor
+    email_verification: web::Data<EmailVerification>,
+    ruleset: web::Data<Ruleset>,
+    metrics: web::Data<Metrics>,
+    query: web::Query<BattleLogQuery>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Battles)?;
+    auth_user.authorize_for(activity.actor)?;
+    metrics.record_inbox_activity("BattleRequest");
+
+    receive_battle_request(
+        activity.into_inner(),
+        pool,
+        email_verification,
+        ruleset,
+        metrics,
+        auth_user.id,
+        query.log,
     )
-    .fetch_one(pool.get_ref())
-    .await;
+    .await
+}
 
-    if actor_fleet.is_err() {
-        return HttpResponse::BadRequest().body("Actor fleet not found");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::json;
+
+    fn builtin_ruleset() -> Ruleset {
+        Ruleset::from_config(&Config::default()).expect("built-in ruleset is valid")
     }
 
-    let actor_fleet = actor_fleet.unwrap();
+    fn fleet(ships: i32, fighters: i32, bombers: i32) -> Fleet {
+        Fleet {
+            ships: Some(ships),
+            fighters: Some(fighters),
+            bombers: Some(bombers),
+        }
+    }
 
-    // Construct the activity with the fleet from the database
-    let battle_request = BattleRequestActivity {
-        activity_type: activity.activity_type.clone(),
-        actor: activity.actor.clone(),
-        target: activity.target.clone(),
-        fleet: actor_fleet,
-        seed: activity.seed,
-    };
+    #[test]
+    fn same_inputs_give_the_same_battle() {
+        let ruleset = builtin_ruleset();
+        let (a, b) = (fleet(10, 50, 20), fleet(15, 30, 10));
+
+        let first = simulate_battle(a.clone(), b.clone(), 42, &ruleset);
+        let second = simulate_battle(a, b, 42, &ruleset);
+
+        assert_eq!(first.winner, second.winner);
+        assert_eq!(first.rounds, second.rounds);
+        assert_eq!(
+            serde_json::to_value(first.fleets_after()).unwrap(),
+            serde_json::to_value(second.fleets_after()).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&first.log).unwrap(),
+            serde_json::to_value(&second.log).unwrap()
+        );
+    }
 
-    // Send the battle request to the target inbox
-    let target_inbox = format!(
-        "http://127.0.0.1:8080/actor/{}@localhost/inbox",
-        activity.target
-    );
-
-    match send_battle_request(battle_request, &target_inbox).await {
-        Ok(_) => HttpResponse::Ok().body("Battle request sent successfully"),
-        Err(e) => {
-            eprintln!("Error sending battle request: {:?}", e);
-            HttpResponse::InternalServerError().body("Failed to send battle request")
-        }
+    /// Pinned outcome for the built-in ruleset. If this fails after a `rand` or
+    /// `rand_pcg` upgrade, `gen_range` changed: bump `SIMULATOR_VERSION` and keep the
+    /// old math reachable from `replay_battle` before updating the expected values.
+    #[test]
+    fn builtin_ruleset_outcome_is_pinned() {
+        let outcome = simulate_battle(fleet(10, 50, 20), fleet(15, 30, 10), 42, &builtin_ruleset());
+
+        assert_eq!(outcome.winner, "Player A");
+        assert_eq!(outcome.rounds, 6);
+        assert_eq!(
+            serde_json::to_value(outcome.fleets_after()).unwrap(),
+            json!({
+                "player_a": {"ships": 10, "fighters": 0, "bombers": 12},
+                "player_b": {"ships": 0, "fighters": 0, "bombers": 0},
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&outcome.log[0]).unwrap(),
+            json!({
+                "round": 1,
+                "player_a": {
+                    "rolls": {"ships": 89, "fighters": 111, "bombers": 95},
+                    "damage_dealt": {"ships": 713, "fighters": 1000, "bombers": 467},
+                    "units_lost": {"ships": 0, "fighters": 26, "bombers": 2},
+                },
+                "player_b": {
+                    "rolls": {"ships": 101, "fighters": 95, "bombers": 118},
+                    "damage_dealt": {"ships": 242, "fighters": 1155, "bombers": 452},
+                    "units_lost": {"ships": 1, "fighters": 23, "bombers": 3},
+                },
+            })
+        );
     }
 }
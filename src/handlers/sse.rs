@@ -1,24 +1,31 @@
-use actix_web::{HttpRequest, HttpResponse, Responder};
+use crate::metrics::{ConnectionGuard, Metrics};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use bytes::Bytes;
 use futures_util::stream::unfold;
 use std::time::Duration;
 use tokio::time::sleep;
 
-pub async fn sse_endpoint(_req: HttpRequest) -> impl Responder {
+pub async fn sse_endpoint(_req: HttpRequest, metrics: web::Data<Metrics>) -> impl Responder {
+    // Counted in `sse_connections` until the client disconnects and the stream is dropped
+    let connection = ConnectionGuard::new(&metrics.sse_connections);
+
     // We start counting from 0 and increment each iteration
-    let s = unfold(0, move |mut counter| async move {
-        // Wait 2 seconds
-        sleep(Duration::from_secs(2)).await;
+    let s = unfold(
+        (0, connection),
+        move |(mut counter, connection)| async move {
+            // Wait 2 seconds
+            sleep(Duration::from_secs(2)).await;
 
-        // Build SSE-formatted string
-        let msg = format!("data: {}\n\n", counter);
-        counter += 1;
+            // Build SSE-formatted string
+            let msg = format!("data: {}\n\n", counter);
+            counter += 1;
 
-        // The unfold returns (Item, NextState)
-        // Item must be `Result<Bytes, std::io::Error>` so Actix can handle it
-        let item = Ok::<Bytes, std::io::Error>(Bytes::from(msg));
-        Some((item, counter))
-    });
+            // The unfold returns (Item, NextState)
+            // Item must be `Result<Bytes, std::io::Error>` so Actix can handle it
+            let item = Ok::<Bytes, std::io::Error>(Bytes::from(msg));
+            Some((item, (counter, connection)))
+        },
+    );
 
     // The `streaming` method expects `Stream<Item=Result<Bytes,E>>`
     HttpResponse::Ok()
@@ -0,0 +1,280 @@
+use crate::auth;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::auth::throttle::{self, FailedLogin};
+use crate::auth::totp;
+use crate::client_ip;
+use crate::error::AppError;
+use crate::handlers::user;
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Shown as the account issuer in authenticator apps.
+const TOTP_ISSUER: &str = "Multiplayer Backend";
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodeDto {
+    pub code: String,
+}
+
+/// Start enrollment: store a new pending secret and return it as an otpauth URI.
+/// Calling it again before `/2fa/confirm` replaces the pending secret.
+pub async fn setup_two_factor(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let user = sqlx::query!(
+        "SELECT username, totp_enabled_at FROM users WHERE id = $1",
+        auth_user.id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::unauthorized("User not found"))?;
+
+    if user.totp_enabled_at.is_some() {
+        return Err(AppError::conflict(
+            "Two-factor authentication is already enabled",
+        ));
+    }
+
+    let secret = totp::generate_secret();
+
+    sqlx::query!(
+        "UPDATE users SET totp_secret = $1 WHERE id = $2",
+        secret,
+        auth_user.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "otpauth_uri": totp::otpauth_uri(&secret, &user.username, TOTP_ISSUER),
+        "secret": secret,
+    })))
+}
+
+/// Finish enrollment with a code from the authenticator app. Returns the
+/// recovery codes; this is the only time they are shown in plain text.
+pub async fn confirm_two_factor(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+    form: web::Json<TwoFactorCodeDto>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let mut tx = pool.begin().await?;
+
+    let user = sqlx::query!(
+        "SELECT totp_secret, totp_enabled_at FROM users WHERE id = $1 FOR UPDATE",
+        auth_user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::unauthorized("User not found"))?;
+
+    if user.totp_enabled_at.is_some() {
+        return Err(AppError::conflict(
+            "Two-factor authentication is already enabled",
+        ));
+    }
+
+    let secret = user
+        .totp_secret
+        .ok_or_else(|| AppError::bad_request("Call /2fa/setup first"))?;
+
+    let step = totp::verify(&secret, &form.code, Utc::now().timestamp() as u64)
+        .ok_or_else(|| AppError::bad_request("Invalid code"))?;
+
+    sqlx::query!(
+        "UPDATE users SET totp_enabled_at = now(), totp_last_step = $1 WHERE id = $2",
+        step as i64,
+        auth_user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let recovery_codes = totp::generate_recovery_codes(RECOVERY_CODE_COUNT);
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| auth::token::hash_token(&totp::normalize_recovery_code(code)))
+        .collect();
+
+    sqlx::query!(
+        "DELETE FROM recovery_codes WHERE user_id = $1",
+        auth_user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (id, user_id, code_hash)
+        SELECT gen_random_uuid(), $1, code_hash
+        FROM UNNEST($2::text[]) AS code_hash
+        "#,
+        auth_user.id,
+        &hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "two_factor_enabled": true,
+        "recovery_codes": recovery_codes,
+    })))
+}
+
+/// Turn 2FA off. Requires a current TOTP code or a recovery code; wrong codes count
+/// against the account and client IP like failed logins, so they can't be brute-forced
+/// with a stolen access token.
+pub async fn disable_two_factor(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    auth_user: AuthenticatedUser,
+    form: web::Json<TwoFactorCodeDto>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", auth_user.id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| AppError::unauthorized("User not found"))?
+        .unwrap_or_default();
+
+    let ip = client_ip::client_ip(&req);
+    let throttle_keys = user::throttle_keys(&email, ip.as_deref());
+    user::ensure_not_locked(
+        pool.get_ref(),
+        &throttle_keys,
+        Some(auth_user.id),
+        &email,
+        ip.as_deref(),
+    )
+    .await?;
+
+    let verified = verify_second_factor(
+        pool.get_ref(),
+        auth_user.id,
+        &form.code,
+        Utc::now().timestamp() as u64,
+    )
+    .await?;
+
+    if !verified {
+        let attempt = FailedLogin {
+            user_id: Some(auth_user.id),
+            email: &email,
+            ip: ip.as_deref(),
+            reason: "invalid_code",
+        };
+        throttle::record_failure(pool.get_ref(), &throttle_keys, attempt).await?;
+        return Err(AppError::bad_request("Invalid code"));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+        WHERE id = $1
+        "#,
+        auth_user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM recovery_codes WHERE user_id = $1",
+        auth_user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Check a second factor for a user with 2FA enabled at `unix_time`.
+/// A TOTP code is accepted once per time step; a recovery code is consumed.
+#[tracing::instrument(target = "db", level = "debug", skip_all, fields(%user_id))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+    unix_time: u64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let user = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_step
+        FROM users
+        WHERE id = $1 AND totp_enabled_at IS NOT NULL
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(user) = user else {
+        return Ok(false);
+    };
+
+    // 1) TOTP code, refusing steps at or before the last accepted one
+    let step = user
+        .totp_secret
+        .as_deref()
+        .and_then(|secret| totp::verify_new_step(secret, code, unix_time, user.totp_last_step));
+
+    if let Some(step) = step {
+        sqlx::query!(
+            "UPDATE users SET totp_last_step = $1 WHERE id = $2",
+            step as i64,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        return Ok(true);
+    }
+
+    // 2) Unused recovery code; the user row lock above serializes redemptions
+    let unused = sqlx::query!(
+        "SELECT id, code_hash FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let hashes: Vec<String> = unused.iter().map(|row| row.code_hash.clone()).collect();
+    let Some(index) = totp::find_recovery_code(code, &hashes) else {
+        return Ok(false);
+    };
+
+    sqlx::query!(
+        "UPDATE recovery_codes SET used_at = now() WHERE id = $1",
+        unused[index].id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/2fa/setup", web::post().to(setup_two_factor));
+    cfg.route("/2fa/confirm", web::post().to(confirm_two_factor));
+    cfg.route("/2fa/disable", web::post().to(disable_two_factor));
+}
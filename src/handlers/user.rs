@@ -1,10 +1,21 @@
 use crate::auth;
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::auth::jwt::{JwtKeys, TokenUse};
+use crate::auth::password::{Hasher, PasswordError, Verified};
+use crate::auth::roles::parse_roles;
+use crate::auth::throttle::{self, FailedLogin, ThrottleKey};
+use crate::client_ip;
+use crate::config::{EmailVerification, PublicUrl, TokenLifetimes};
+use crate::error::AppError;
+use crate::handlers::{account, two_factor, verification};
+use crate::mail::Mailer;
+use crate::metrics::Metrics;
 use crate::models::user::User;
-use actix_web::{Error, HttpRequest, HttpResponse, web};
+use crate::validation::{self, ValidationErrors};
+use actix_web::{HttpRequest, HttpResponse, web};
 use chrono::{Duration, Utc};
-use rand::RngCore; // for generating random tokens
-use rand::rngs::OsRng;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 
 use actix_web::web::ServiceConfig;
@@ -23,15 +34,41 @@ pub struct RegisterDto {
     pub password: String,
 }
 
-// Example: Insert user
+/// Create an account and email a verification token. The response carries an access
+/// token unless `email_verification` is `login`; then the player verifies and logs in.
+#[allow(clippy::too_many_arguments)] // one per extractor
 pub async fn register_user(
     pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    hasher: web::Data<Hasher>,
     form: web::Json<RegisterDto>,
-) -> Result<HttpResponse, Error> {
-    let hashed = auth::password::hash_password(&form.password);
+    jwt_keys: web::Data<JwtKeys>,
+    public_url: web::Data<PublicUrl>,
+    tokens: web::Data<TokenLifetimes>,
+    email_verification: web::Data<EmailVerification>,
+) -> Result<HttpResponse, AppError> {
+    let mut errors = ValidationErrors::default();
+    validation::check_username(&mut errors, &form.username);
+    validation::check_email(&mut errors, &form.email);
+    validation::check_password(
+        &mut errors,
+        "password",
+        &form.password,
+        &[&form.username, &form.email],
+    );
 
     // Append domain to the username
-    let username_with_domain = format!("{}@localhost", form.username);
+    let username_with_domain = public_url.username(&form.username);
+    if account::username_was_deleted(pool.get_ref(), &username_with_domain).await? {
+        errors.add("username", "This name belonged to a deleted account");
+    }
+    errors.into_result()?;
+
+    let hashed = hasher.hash_password(&form.password)?;
+
+    // The user and their verification token are stored together, so a failure leaves
+    // no half-registered account behind and the client can retry with the same email
+    let mut tx = pool.begin().await?;
 
     // Use explicit casts for returning columns:
     // e.g.  id as "id: Uuid", created_at as "created_at: chrono::DateTime<Utc>"
@@ -43,8 +80,12 @@ pub async fn register_user(
     RETURNING
       id         as "id: Uuid",
       username   as "username!",
-      email      as "email!",
-      password   as "password!",
+      email,
+      password,
+      roles      as "roles!",
+      email_verified_at,
+      totp_enabled_at,
+      deletion_scheduled_at,
       created_at as "created_at!: chrono::DateTime<Utc>"
     "#,
         Uuid::new_v4(),
@@ -52,17 +93,32 @@ pub async fn register_user(
         form.email,
         hashed
     )
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?; // Generate JWT or do other logic...
-
-    let token = auth::jwt::generate_jwt(&inserted_user.id.to_string(), "secret");
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let verification_token =
+        verification::create_verification_token(&mut tx, &tokens, inserted_user.id).await?;
+    tx.commit().await?;
+
+    // Ask the player to confirm the address
+    verification::mail_verification_token(mailer, &tokens, &form.email, &verification_token).await;
+
+    // With `login`, an unverified account gets no token until it is verified
+    let token = (**email_verification != EmailVerification::Login).then(|| {
+        auth::jwt::generate_jwt(
+            &inserted_user.id.to_string(),
+            &parse_roles(&inserted_user.roles),
+            &jwt_keys,
+        )
+    });
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "user": {
             "id": inserted_user.id,
             "username": inserted_user.username,
             "email": inserted_user.email,
+            "roles": inserted_user.roles,
+            "email_verified": false,
             "created_at": inserted_user.created_at,
         },
         "token": token
@@ -73,145 +129,416 @@ pub async fn register_user(
 pub struct LoginDto {
     pub email: String,
     pub password: String,
+    pub device_label: Option<String>, // e.g. "Steam Deck"; defaults to the User-Agent
 }
 
+#[allow(clippy::too_many_arguments)] // one per extractor
 pub async fn login_user(
     pool: web::Data<PgPool>,
+    req: HttpRequest,
+    hasher: web::Data<Hasher>,
     form: web::Json<LoginDto>,
-    jwt_secret: web::Data<String>, // from .env
-) -> Result<HttpResponse, Error> {
-    // 1) Find user by email
+    jwt_keys: web::Data<JwtKeys>,
+    tokens: web::Data<TokenLifetimes>,
+    email_verification: web::Data<EmailVerification>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, AppError> {
+    // 1) Refuse outright while the account or the client IP is locked out
+    let ip = client_ip::client_ip(&req);
+    let throttle_keys = throttle_keys(&form.email, ip.as_deref());
+    ensure_not_locked(
+        pool.get_ref(),
+        &throttle_keys,
+        None,
+        &form.email,
+        ip.as_deref(),
+    )
+    .await?;
+
+    // 2) Find user by email
     let user = sqlx::query_as!(
         User,
         r#"
         SELECT 
           id         as "id: Uuid",
           username   as "username!",
-          email      as "email!",
-          password   as "password!",
+          email,
+          password,
+          roles      as "roles!",
+          email_verified_at,
+          totp_enabled_at,
+          deletion_scheduled_at,
           created_at as "created_at!: chrono::DateTime<Utc>"
         FROM users
-        WHERE email = $1
+        WHERE lower(email) = lower($1)
         "#,
         form.email
     )
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid credentials"))?;
+    .fetch_optional(pool.get_ref())
+    .await?;
+
+    let Some(user) = user else {
+        let attempt = FailedLogin {
+            user_id: None,
+            email: &form.email,
+            ip: ip.as_deref(),
+            reason: "unknown_account",
+        };
+        metrics.record_login(false);
+        throttle::record_failure(pool.get_ref(), &throttle_keys, attempt).await?;
+        return Err(AppError::unauthorized("Invalid credentials"));
+    };
+
+    // 3) Check password
+    let verified = match user.password.as_deref() {
+        Some(hash) => hasher.verify_password(hash, &form.password),
+        None => Err(PasswordError::Mismatch),
+    };
+    let verified = match verified {
+        Ok(verified) => verified,
+        Err(PasswordError::Mismatch) => {
+            let attempt = FailedLogin {
+                user_id: Some(user.id),
+                email: &form.email,
+                ip: ip.as_deref(),
+                reason: "invalid_password",
+            };
+            metrics.record_login(false);
+            throttle::record_failure(pool.get_ref(), &throttle_keys, attempt).await?;
+            return Err(AppError::unauthorized("Invalid credentials"));
+        }
+        Err(e) => {
+            tracing::error!(user_id = %user.id, error = %e, "Cannot verify password");
+            let attempt = FailedLogin {
+                user_id: Some(user.id),
+                email: &form.email,
+                ip: ip.as_deref(),
+                reason: "malformed_hash",
+            };
+            metrics.record_login(false);
+            throttle::record_failure(pool.get_ref(), &throttle_keys, attempt).await?;
+            return Err(AppError::unauthorized("Invalid credentials"));
+        }
+    };
 
-    // 2) Check password
-    if !auth::password::verify_password(&user.password, &form.password) {
-        return Err(actix_web::error::ErrorUnauthorized("Invalid credentials"));
+    // Upgrade hashes made with older Argon2 settings while we have the plaintext
+    if verified == Verified::Outdated {
+        rehash_password(pool.get_ref(), &hasher, &user, &form.password).await;
     }
 
-    // 3) Generate short-lived access token
-    let access_token = auth::jwt::generate_jwt(&user.id.to_string(), &jwt_secret);
+    ensure_may_sign_in(
+        **email_verification,
+        user.email_verified_at,
+        user.deletion_scheduled_at,
+    )?;
+
+    // 4) With 2FA enabled, hand out a short-lived token for /login/2fa instead of a session.
+    //    The account's failure count is only cleared once the second factor checks out.
+    if user.totp_enabled_at.is_some() {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "mfa_required": true,
+            "mfa_token": auth::jwt::generate_mfa_pending_jwt(&user.id.to_string(), &jwt_keys),
+        })));
+    }
 
-    // 4) Generate refresh token
-    let refresh_str = generate_refresh_token();
-    // store in DB with an expiry, e.g. 14 days from now
-    let refresh_exp = Utc::now() + Duration::days(14);
-    let rt_id = Uuid::new_v4();
+    // 5) Issue access & refresh tokens
+    throttle::record_success(pool.get_ref(), &form.email).await?;
+    metrics.record_login(true);
 
-    sqlx::query!(
+    let device_label = form.device_label.clone().or_else(|| user_agent(&req));
+    start_session(pool.get_ref(), &req, &jwt_keys, &tokens, user, device_label).await
+}
+
+#[derive(serde::Deserialize)]
+pub struct LoginTwoFactorDto {
+    pub mfa_token: String, // from the /login response
+    pub code: String,      // current TOTP code or an unused recovery code
+    pub device_label: Option<String>,
+}
+
+/// Second step of login for accounts with 2FA enabled.
+pub async fn login_two_factor(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    form: web::Json<LoginTwoFactorDto>,
+    jwt_keys: web::Data<JwtKeys>,
+    tokens: web::Data<TokenLifetimes>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, AppError> {
+    // 1) The MFA token proves the password step succeeded a few minutes ago
+    let user_id = auth::jwt::decode_jwt(&form.mfa_token, &jwt_keys)
+        .ok()
+        .filter(|claims| claims.token_use == TokenUse::MfaPending)
+        .and_then(|claims| Uuid::parse_str(&claims.sub).ok())
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired MFA token"))?;
+
+    let user = sqlx::query_as!(
+        User,
         r#"
-        INSERT INTO refresh_tokens (id, user_id, token, expires_at)
-        VALUES ($1, $2, $3, $4)
+        SELECT 
+          id         as "id: Uuid",
+          username   as "username!",
+          email,
+          password,
+          roles      as "roles!",
+          email_verified_at,
+          totp_enabled_at,
+          deletion_scheduled_at,
+          created_at as "created_at!: chrono::DateTime<Utc>"
+        FROM users
+        WHERE id = $1
         "#,
-        rt_id,
-        user.id,
-        refresh_str,
-        refresh_exp
+        user_id
     )
-    .execute(pool.get_ref())
-    .await
-    .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::unauthorized("User not found"))?;
+
+    // 2) Code guesses count against the same account and IP as password guesses
+    let ip = client_ip::client_ip(&req);
+    let email = user.email.clone().unwrap_or_default();
+    let throttle_keys = throttle_keys(&email, ip.as_deref());
+    ensure_not_locked(
+        pool.get_ref(),
+        &throttle_keys,
+        Some(user.id),
+        &email,
+        ip.as_deref(),
+    )
+    .await?;
+
+    // 3) Check the TOTP or recovery code
+    let verified = two_factor::verify_second_factor(
+        pool.get_ref(),
+        user_id,
+        &form.code,
+        Utc::now().timestamp() as u64,
+    )
+    .await?;
+
+    if !verified {
+        let attempt = FailedLogin {
+            user_id: Some(user.id),
+            email: &email,
+            ip: ip.as_deref(),
+            reason: "invalid_code",
+        };
+        metrics.record_login(false);
+        throttle::record_failure(pool.get_ref(), &throttle_keys, attempt).await?;
+        return Err(AppError::unauthorized("Invalid code"));
+    }
 
-    // 5) Return JSON with both tokens
-    let resp = serde_json::json!({
-        "access_token": access_token,
-        "refresh_token": refresh_str,
-        "user": {
-            "id": user.id,
-            "username": user.username,
-            "email": user.email,
-            "created_at": user.created_at
+    // 4) Issue access & refresh tokens
+    throttle::record_success(pool.get_ref(), &email).await?;
+    metrics.record_login(true);
+
+    let device_label = form.device_label.clone().or_else(|| user_agent(&req));
+    start_session(pool.get_ref(), &req, &jwt_keys, &tokens, user, device_label).await
+}
+
+/// Replace the stored hash with one using the current parameters. Failures are only
+/// logged; the player can still log in with the old hash.
+async fn rehash_password(pool: &PgPool, hasher: &Hasher, user: &User, password: &str) {
+    let hashed = match hasher.hash_password(password) {
+        Ok(hashed) => hashed,
+        Err(e) => {
+            tracing::warn!(user_id = %user.id, error = %e, "Cannot rehash password");
+            return;
         }
-    });
+    };
+
+    // Only if the password wasn't changed in the meantime
+    let result = sqlx::query!(
+        "UPDATE users SET password = $1 WHERE id = $2 AND password = $3",
+        hashed,
+        user.id,
+        user.password
+    )
+    .execute(pool)
+    .await;
 
-    Ok(HttpResponse::Ok().json(resp))
+    if let Err(e) = result {
+        tracing::warn!(user_id = %user.id, error = %e, "Cannot store rehashed password");
+    }
 }
 
-pub async fn get_me(
-    pool: web::Data<PgPool>,
-    req: HttpRequest,
-    jwt_secret: web::Data<String>, // <-- retrieve from App data
-) -> Result<HttpResponse, Error> {
-    // 1) Grab Authorization header
-    let auth_header = req.headers().get("Authorization");
-    if auth_header.is_none() {
-        return Err(actix_web::error::ErrorUnauthorized("Missing token"));
+/// The account and the client IP a failed attempt counts against, where known.
+/// Guests have no email, so they are only throttled by IP rather than sharing one key.
+pub fn throttle_keys(email: &str, ip: Option<&str>) -> Vec<ThrottleKey> {
+    let mut keys = Vec::new();
+    if !email.trim().is_empty() {
+        keys.push(ThrottleKey::Account(email.to_string()));
     }
+    keys.extend(ip.map(|ip| ThrottleKey::Ip(ip.to_string())));
+    keys
+}
 
-    let header_str = auth_header
-        .unwrap()
-        .to_str()
-        .map_err(|_| actix_web::error::ErrorUnauthorized("Bad auth header"))?;
-    if !header_str.starts_with("Bearer ") {
-        return Err(actix_web::error::ErrorUnauthorized("Invalid token format"));
+/// Refuse new tokens for an account that must verify its email first, or whose deletion
+/// grace period is over (it is purged shortly). Accounts still in the grace period may
+/// sign in, so they can cancel the deletion.
+fn ensure_may_sign_in(
+    email_verification: EmailVerification,
+    email_verified_at: Option<chrono::DateTime<Utc>>,
+    deletion_scheduled_at: Option<chrono::DateTime<Utc>>,
+) -> Result<(), AppError> {
+    if deletion_scheduled_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(AppError::unauthorized("Account deleted"));
     }
+    if email_verification == EmailVerification::Login && email_verified_at.is_none() {
+        return Err(AppError::forbidden("Email address not verified"));
+    }
+    Ok(())
+}
 
-    let token = &header_str[7..];
+/// 429 if any of `keys` is locked out; the rejected attempt is still audited.
+pub async fn ensure_not_locked(
+    pool: &PgPool,
+    keys: &[ThrottleKey],
+    user_id: Option<Uuid>,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    let locked_for = throttle::locked_for(pool, keys).await?;
+
+    let Some(retry_after) = locked_for else {
+        return Ok(());
+    };
+
+    let attempt = FailedLogin {
+        user_id,
+        email,
+        ip,
+        reason: "locked",
+    };
+    throttle::record_locked(pool, attempt).await?;
+
+    Err(AppError::TooManyRequests { retry_after })
+}
 
-    // 2) Decode the JWT
-    // Instead of a hard-coded "secret", use the real secret from .env
-    let claims = auth::jwt::decode_jwt(token, &jwt_secret)
-        .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid token"))?;
+/// Issue an access token and a refresh token starting a new token family.
+pub async fn start_session(
+    pool: &PgPool,
+    req: &HttpRequest,
+    jwt_keys: &JwtKeys,
+    tokens: &TokenLifetimes,
+    user: User,
+    device_label: Option<String>,
+) -> Result<HttpResponse, AppError> {
+    let access_token =
+        auth::jwt::generate_jwt(&user.id.to_string(), &parse_roles(&user.roles), jwt_keys);
+
+    let (_, refresh_token) = issue_refresh_token(
+        pool,
+        tokens,
+        user.id,
+        Uuid::new_v4(),
+        device_label.as_deref(),
+        client_ip::client_ip(req).as_deref(),
+    )
+    .await?;
 
-    // 3) Extract user ID from claims
-    let user_id = Uuid::parse_str(&claims.sub)
-        .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid user ID"))?;
+    Ok(HttpResponse::Ok().json(AuthResponse {
+        access_token,
+        refresh_token,
+        user,
+    }))
+}
+
+pub async fn get_me(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_scope(Scope::Profile)?;
 
-    // 4) Query DB for user
+    // 1) Query DB for the authenticated user
     let user = sqlx::query_as!(
         User,
         r#"
         SELECT 
           id         as "id: Uuid",
           username   as "username!",
-          email      as "email!",
-          password   as "password!",
+          email,
+          password,
+          roles      as "roles!",
+          email_verified_at,
+          totp_enabled_at,
+          deletion_scheduled_at,
           created_at as "created_at!: chrono::DateTime<Utc>"
         FROM users
         WHERE id = $1
         "#,
-        user_id
+        auth_user.id
     )
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorUnauthorized("User not found"))?;
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::unauthorized("User not found"))?;
 
-    // 5) Return user (omitting password)
+    // 2) Return user (omitting password)
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "id": user.id,
         "username": user.username,
         "email": user.email,
+        "roles": user.roles,
+        "guest": user.is_guest(),
+        "email_verified": user.email_verified_at.is_some(),
+        "two_factor_enabled": user.totp_enabled_at.is_some(),
+        "deletion_scheduled_at": user.deletion_scheduled_at,
         "created_at": user.created_at
     })))
 }
 
+pub fn user_agent(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+}
+
 pub fn config(cfg: &mut ServiceConfig) {
     cfg.route("/register", web::post().to(register_user));
     cfg.route("/login", web::post().to(login_user));
+    cfg.route("/login/2fa", web::post().to(login_two_factor));
     cfg.route("/me", web::get().to(get_me));
     cfg.route("/refresh", web::post().to(refresh_token));
+    cfg.route("/logout", web::post().to(logout));
+    cfg.route("/logout-all", web::post().to(logout_all));
+    cfg.route("/sessions", web::get().to(list_sessions));
+    cfg.route("/sessions/{id}", web::delete().to(revoke_session));
 }
 
-// For the refresh token
-pub fn generate_refresh_token() -> String {
-    let mut bytes = [0u8; 32];
-    OsRng.fill_bytes(&mut bytes);
-    hex::encode(bytes) // a hex string like "aabbcc..."
+/// Store a new refresh token in `family_id`, valid for `tokens.refresh_days`.
+/// Returns the row id together with the token string.
+#[tracing::instrument(target = "db", level = "debug", skip_all, fields(%user_id, %family_id))]
+async fn issue_refresh_token(
+    executor: impl PgExecutor<'_>,
+    tokens: &TokenLifetimes,
+    user_id: Uuid,
+    family_id: Uuid,
+    device_label: Option<&str>,
+    ip_address: Option<&str>,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let id = Uuid::new_v4();
+    let token = auth::token::generate_token();
+    let expires_at = Utc::now() + Duration::days(tokens.refresh_days.into());
+
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens
+          (id, user_id, token, expires_at, family_id, device_label, ip_address, last_used_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now())
+        "#,
+        id,
+        user_id,
+        token,
+        expires_at,
+        family_id,
+        device_label,
+        ip_address
+    )
+    .execute(executor)
+    .await?;
+
+    Ok((id, token))
 }
 
 #[derive(serde::Deserialize)]
@@ -219,47 +546,223 @@ pub struct RefreshDto {
     pub refresh_token: String,
 }
 
+/// Exchange a refresh token for a new access token and a new refresh token.
+///
+/// The presented token is revoked on use. Presenting a token that was already
+/// rotated means it leaked, so the whole family is revoked and the player has
+/// to log in again.
 pub async fn refresh_token(
     pool: web::Data<PgPool>,
+    req: HttpRequest,
     form: web::Json<RefreshDto>,
-    jwt_secret: web::Data<String>,
-) -> Result<HttpResponse, Error> {
-    // 1) find refresh token row
+    jwt_keys: web::Data<JwtKeys>,
+    tokens: web::Data<TokenLifetimes>,
+    email_verification: web::Data<EmailVerification>,
+) -> Result<HttpResponse, AppError> {
+    let mut tx = pool.begin().await?;
+
+    // 1) find and lock the refresh token row
     let row = sqlx::query!(
         r#"
-        SELECT rt.user_id, rt.expires_at, u.username, u.email, u.password, u.created_at
+        SELECT
+          rt.id, rt.user_id, rt.family_id, rt.expires_at, rt.revoked_at, rt.device_label,
+          u.username, u.email, u.password, u.roles, u.email_verified_at, u.totp_enabled_at,
+          u.deletion_scheduled_at,
+          u.created_at as "created_at!: chrono::DateTime<Utc>"
         FROM refresh_tokens rt
         JOIN users u ON rt.user_id = u.id
         WHERE rt.token = $1
+        FOR UPDATE OF rt
         "#,
         form.refresh_token
     )
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid refresh token"))?;
-
-    // 2) check expiry
-    let now = Utc::now();
-    if row.expires_at < now {
-        return Err(actix_web::error::ErrorUnauthorized("Refresh token expired"));
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::unauthorized("Invalid refresh token"))?;
+
+    // 2) reuse detection: an already rotated token revokes the whole family
+    if row.revoked_at.is_some() {
+        sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = now()
+            WHERE family_id = $1 AND revoked_at IS NULL
+            "#,
+            row.family_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        tracing::warn!(
+            user_id = %row.user_id,
+            family_id = %row.family_id,
+            "Refresh token reuse detected, revoked family"
+        );
+        return Err(AppError::unauthorized("Refresh token reuse detected"));
     }
 
-    // 3) generate a new short-lived access token
-    let new_access = auth::jwt::generate_jwt(&row.user_id.to_string(), &jwt_secret);
+    // 3) check expiry
+    if row.expires_at < Utc::now() {
+        return Err(AppError::unauthorized("Refresh token expired"));
+    }
 
-    // 4) optional: rotate refresh token or keep the same. For simplicity, let's keep it the same.
+    // 4) the account must still be allowed to sign in, e.g. if `login` verification
+    //    was switched on after this session started
+    ensure_may_sign_in(
+        **email_verification,
+        row.email_verified_at,
+        row.deletion_scheduled_at,
+    )?;
+
+    // 5) rotate: issue a new token in the same family and retire the old one
+    let (new_id, new_refresh) = issue_refresh_token(
+        &mut *tx,
+        &tokens,
+        row.user_id,
+        row.family_id,
+        row.device_label.as_deref(),
+        client_ip::client_ip(&req).as_deref(),
+    )
+    .await?;
 
-    // 5) return the new access token & existing refresh token
-    let resp = serde_json::json!({
-        "access_token": new_access,
-        "refresh_token": form.refresh_token, // or a new one if rotating
-        "user": {
-            "id": row.user_id,
-            "username": row.username,
-            "email": row.email,
-            "created_at": row.created_at
-        }
-    });
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now(), replaced_by = $2
+        WHERE id = $1
+        "#,
+        row.id,
+        new_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    // 6) generate a new short-lived access token
+    let new_access = auth::jwt::generate_jwt(
+        &row.user_id.to_string(),
+        &parse_roles(&row.roles),
+        &jwt_keys,
+    );
+
+    // 7) return the new access token & the rotated refresh token
+    Ok(HttpResponse::Ok().json(AuthResponse {
+        access_token: new_access,
+        refresh_token: new_refresh,
+        user: User {
+            id: row.user_id,
+            username: row.username,
+            email: row.email,
+            password: row.password,
+            roles: row.roles,
+            email_verified_at: row.email_verified_at,
+            totp_enabled_at: row.totp_enabled_at,
+            deletion_scheduled_at: row.deletion_scheduled_at,
+            created_at: row.created_at,
+        },
+    }))
+}
+
+#[derive(serde::Serialize)]
+pub struct SessionDto {
+    pub id: Uuid, // the token family; stays the same across refreshes
+    pub device_label: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub last_used_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// Revoke the session the given refresh token belongs to.
+pub async fn logout(
+    pool: web::Data<PgPool>,
+    form: web::Json<RefreshDto>,
+) -> Result<HttpResponse, AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now()
+        WHERE revoked_at IS NULL
+          AND family_id = (SELECT family_id FROM refresh_tokens WHERE token = $1)
+        "#,
+        form.refresh_token
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Revoke every refresh token of the authenticated user.
+pub async fn logout_all(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+        auth_user.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List the active sessions (unrevoked, unexpired token families) of the authenticated user.
+pub async fn list_sessions(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let sessions = sqlx::query_as!(
+        SessionDto,
+        r#"
+        SELECT
+          rt.family_id as "id",
+          rt.device_label,
+          rt.ip_address,
+          (SELECT min(f.created_at) FROM refresh_tokens f WHERE f.family_id = rt.family_id)
+            as "created_at!: chrono::DateTime<Utc>",
+          rt.last_used_at
+        FROM refresh_tokens rt
+        WHERE rt.user_id = $1 AND rt.revoked_at IS NULL AND rt.expires_at > now()
+        ORDER BY rt.last_used_at DESC
+        "#,
+        auth_user.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Revoke one session of the authenticated user, e.g. a stolen one.
+pub async fn revoke_session(
+    pool: web::Data<PgPool>,
+    auth_user: AuthenticatedUser,
+    session_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = now()
+        WHERE family_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id.into_inner(),
+        auth_user.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found("Session not found"));
+    }
 
-    Ok(HttpResponse::Ok().json(resp))
+    Ok(HttpResponse::NoContent().finish())
 }
@@ -0,0 +1,200 @@
+use crate::auth;
+use crate::auth::extractor::AuthenticatedUser;
+use crate::config::{EmailVerification, TokenLifetimes};
+use crate::error::AppError;
+use crate::mail::{self, Email, Mailer};
+use actix_web::{HttpResponse, web};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct VerifyEmailDto {
+    pub token: String,
+}
+
+/// Create a verification token valid for `tokens.email_verification_hours` on `conn`.
+/// Any earlier unused token of the user stops working. Mail the returned token with
+/// `mail_verification_token` once the surrounding transaction has committed.
+#[tracing::instrument(target = "db", level = "debug", skip_all, fields(%user_id))]
+pub async fn create_verification_token(
+    conn: &mut PgConnection,
+    tokens: &TokenLifetimes,
+    user_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let token = auth::token::generate_token();
+    let expires_at = Utc::now() + Duration::hours(tokens.email_verification_hours.into());
+
+    sqlx::query!(
+        "DELETE FROM email_verification_tokens WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_verification_tokens (id, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        auth::token::hash_token(&token),
+        expires_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(token)
+}
+
+/// Email a token from `create_verification_token` to `email`.
+pub async fn mail_verification_token(
+    mailer: web::Data<dyn Mailer>,
+    tokens: &TokenLifetimes,
+    email: &str,
+    token: &str,
+) {
+    let email = Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Welcome aboard, commander!\n\n\
+             Verification token: {}\n\n\
+             POST it to /verify-email within the next {} hours to confirm this address.",
+            token, tokens.email_verification_hours
+        ),
+    };
+    mail::deliver(mailer, email).await;
+}
+
+/// Create a verification token and email it to `email`.
+pub async fn send_verification_email(
+    pool: &PgPool,
+    mailer: web::Data<dyn Mailer>,
+    tokens: &TokenLifetimes,
+    user_id: Uuid,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let token = create_verification_token(&mut tx, tokens, user_id).await?;
+    tx.commit().await?;
+
+    mail_verification_token(mailer, tokens, email, &token).await;
+    Ok(())
+}
+
+/// Redeem a verification token and mark the user's email as verified.
+pub async fn verify_email(
+    pool: web::Data<PgPool>,
+    form: web::Json<VerifyEmailDto>,
+) -> Result<HttpResponse, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query!(
+        r#"
+        SELECT id, user_id, expires_at, used_at
+        FROM email_verification_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        auth::token::hash_token(&form.token)
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let row = match row {
+        Some(row) if row.used_at.is_none() && row.expires_at > Utc::now() => row,
+        _ => {
+            return Err(AppError::bad_request(
+                "Invalid or expired verification token",
+            ));
+        }
+    };
+
+    sqlx::query!(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, now()) WHERE id = $1",
+        row.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE email_verification_tokens SET used_at = now() WHERE id = $1",
+        row.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "email_verified": true })))
+}
+
+/// Send a fresh verification email to the authenticated user.
+pub async fn resend_verification_email(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    tokens: web::Data<TokenLifetimes>,
+    auth_user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    auth_user.require_interactive()?;
+
+    let user = sqlx::query!(
+        "SELECT email, email_verified_at FROM users WHERE id = $1",
+        auth_user.id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::unauthorized("User not found"))?;
+
+    if user.email_verified_at.is_some() {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "email_verified": true })));
+    }
+
+    let email = user
+        .email
+        .ok_or_else(|| AppError::bad_request("Guest accounts have no email address"))?;
+
+    send_verification_email(pool.get_ref(), mailer, &tokens, auth_user.id, &email).await?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "Verification email sent"
+    })))
+}
+
+/// Reject the battle if the policy requires verified emails and a participant has none.
+#[tracing::instrument(target = "db", level = "debug", skip_all)]
+pub async fn ensure_verified_for_battle(
+    pool: &PgPool,
+    policy: EmailVerification,
+    participants: &[Uuid],
+) -> Result<(), AppError> {
+    if policy != EmailVerification::Battle {
+        return Ok(());
+    }
+
+    let unverified = sqlx::query_scalar!(
+        "SELECT count(*) FROM users WHERE id = ANY($1) AND email_verified_at IS NULL",
+        participants
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if unverified.unwrap_or(0) > 0 {
+        return Err(AppError::forbidden(
+            "All participants must verify their email before battling",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/verify-email", web::post().to(verify_email));
+    cfg.route(
+        "/verify-email/resend",
+        web::post().to(resend_verification_email),
+    );
+}
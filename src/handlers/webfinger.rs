@@ -1,4 +1,6 @@
-use actix_web::{HttpResponse, Responder, web};
+use crate::config::PublicUrl;
+use crate::error::AppError;
+use actix_web::{HttpResponse, web};
 use serde_json::json;
 use sqlx::PgPool;
 use std::collections::HashMap;
@@ -6,35 +8,42 @@ use std::collections::HashMap;
 pub async fn webfinger(
     query: web::Query<HashMap<String, String>>,
     pool: web::Data<PgPool>,
-) -> impl Responder {
-    if let Some(resource) = query.get("resource") {
-        if resource.starts_with("acct:") {
-            let parts: Vec<&str> = resource.split('@').collect();
-            if parts.len() == 2 {
-                let username = parts[0].strip_prefix("acct:").unwrap_or("");
-                let domain = parts[1];
+    public_url: web::Data<PublicUrl>,
+) -> Result<HttpResponse, AppError> {
+    // acct:alice@example.com -> the stored username is "alice@example.com"
+    let Some((name, domain)) = query
+        .get("resource")
+        .and_then(|resource| resource.strip_prefix("acct:"))
+        .and_then(|acct| acct.split_once('@'))
+        .filter(|(name, domain)| !name.is_empty() && !domain.is_empty() && !domain.contains('@'))
+    else {
+        return Err(AppError::bad_request(
+            "resource must be of the form acct:name@domain",
+        ));
+    };
 
-                // Query the database for the user
-                let user_exists =
-                    sqlx::query_scalar!("SELECT 1 FROM users WHERE username = $1", username)
-                        .fetch_optional(pool.get_ref())
-                        .await
-                        .is_ok();
+    // Only accounts on this server's domain exist here
+    if !domain.eq_ignore_ascii_case(&public_url.domain) {
+        return Err(AppError::not_found("User not found"));
+    }
+
+    // Query the database for the user
+    let username = sqlx::query_scalar!(
+        "SELECT username FROM users WHERE lower(username) = lower($1)",
+        public_url.username(name)
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
 
-                if user_exists {
-                    return HttpResponse::Ok().json(json!({
-                        "subject": resource,
-                        "links": [
-                            {
-                                "rel": "self",
-                                "type": "application/activity+json",
-                                "href": format!("http://{}/actor/{}", domain, username)
-                            }
-                        ]
-                    }));
-                }
+    Ok(HttpResponse::Ok().json(json!({
+        "subject": format!("acct:{}", username),
+        "links": [
+            {
+                "rel": "self",
+                "type": "application/activity+json",
+                "href": public_url.actor_url(&username)
             }
-        }
-    }
-    HttpResponse::NotFound().finish()
+        ]
+    })))
 }
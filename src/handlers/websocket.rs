@@ -1,9 +1,10 @@
+use crate::metrics::{ConnectionGuard, Metrics};
 use actix::prelude::*;
 use actix_web::{Error, HttpRequest, HttpResponse, web};
 use actix_web_actors::ws;
 
 pub struct MyWs {
-    pub id: usize,
+    _connection: ConnectionGuard, // counted in `websocket_connections` until the actor is dropped
 }
 
 impl Actor for MyWs {
@@ -31,7 +32,13 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
     }
 }
 
-pub async fn ws_index(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-    let ws = MyWs { id: 0 };
+pub async fn ws_index(
+    req: HttpRequest,
+    stream: web::Payload,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, Error> {
+    let ws = MyWs {
+        _connection: ConnectionGuard::new(&metrics.websocket_connections),
+    };
     ws::start(ws, &req, stream)
 }
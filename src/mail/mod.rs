@@ -0,0 +1,65 @@
+pub mod smtp;
+pub mod spool;
+
+use crate::config::Config;
+use actix_web::web;
+use std::fmt;
+use std::sync::Arc;
+
+/// An outgoing plain-text email.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Io(std::io::Error),
+    Smtp(String),
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailError::Io(e) => write!(f, "failed to write mail: {}", e),
+            MailError::Smtp(e) => write!(f, "failed to send mail: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+/// Delivers emails. `send` blocks, so call it through `web::block` from handlers.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+/// Build the mailer selected by `mail.transport` (`spool` or `smtp`).
+pub fn from_config(config: &Config) -> Result<Arc<dyn Mailer>, MailError> {
+    match config.mail.transport.as_str() {
+        "smtp" => Ok(Arc::new(smtp::SmtpMailer::new(
+            &config.mail.from,
+            &config.mail.smtp_host,
+            config.mail.smtp_port,
+            config.mail.smtp_username.as_deref(),
+            config.mail.smtp_password.as_deref(),
+        )?)),
+        _ => Ok(Arc::new(spool::SpoolMailer::new(
+            &config.mail.from,
+            &config.mail.spool_dir,
+        )?)),
+    }
+}
+
+/// Send `email` on the blocking thread pool. Failures are logged, not returned:
+/// callers answer the request the same way whether or not the mail went out.
+pub async fn deliver(mailer: web::Data<dyn Mailer>, email: Email) {
+    let subject = email.subject.clone();
+    match web::block(move || mailer.send(&email)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!(%subject, error = %e, "Failed to send email"),
+        Err(e) => tracing::error!(%subject, error = %e, "Failed to send email"),
+    }
+}
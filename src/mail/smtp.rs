@@ -0,0 +1,57 @@
+use crate::mail::{Email, MailError, Mailer};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+/// Sends email over plain SMTP, e.g. to a local MailHog/Mailpit test server
+/// or a relay on the same network.
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        from: &str,
+        host: &str,
+        port: u16,
+        username: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<Self, MailError> {
+        let from = from
+            .parse()
+            .map_err(|e| MailError::Smtp(format!("invalid sender {}: {}", from, e)))?;
+
+        let mut builder = SmtpTransport::builder_dangerous(host).port(port);
+        if let (Some(username), Some(password)) = (username, password) {
+            builder =
+                builder.credentials(Credentials::new(username.to_string(), password.to_string()));
+        }
+
+        Ok(SmtpMailer {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let to = email
+            .to
+            .parse()
+            .map_err(|e| MailError::Smtp(format!("invalid recipient {}: {}", email.to, e)))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject.clone())
+            .body(email.body.clone())
+            .map_err(|e| MailError::Smtp(e.to_string()))?;
+
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| MailError::Smtp(e.to_string()))
+    }
+}
@@ -0,0 +1,44 @@
+use crate::mail::{Email, MailError, Mailer};
+use chrono::Utc;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Writes every email to `<dir>/<timestamp>-<uuid>.eml` instead of sending it.
+/// The default transport, handy for local development and offline tests.
+pub struct SpoolMailer {
+    from: String,
+    dir: PathBuf,
+}
+
+impl SpoolMailer {
+    pub fn new(from: &str, dir: &str) -> Result<Self, MailError> {
+        fs::create_dir_all(dir).map_err(MailError::Io)?;
+        Ok(SpoolMailer {
+            from: from.to_string(),
+            dir: PathBuf::from(dir),
+        })
+    }
+}
+
+impl Mailer for SpoolMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let now = Utc::now();
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            now.format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4()
+        ));
+
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\r\n{}\r\n",
+            self.from,
+            email.to,
+            email.subject,
+            now.to_rfc2822(),
+            email.body
+        );
+
+        fs::write(path, message).map_err(MailError::Io)
+    }
+}
@@ -1,42 +1,169 @@
 mod auth;
+mod client_ip;
+mod commitment;
 mod config;
+mod error;
 mod handlers;
+mod mail;
+mod metrics;
 mod middleware;
 mod models;
+mod ruleset;
+mod telemetry;
+mod validation;
 
 use actix_web::{App, HttpServer, web};
-use actix_web_httpauth::middleware::HttpAuthentication;
+use auth::jwt::JwtKeys;
+use auth::password::Hasher;
 use config::Config;
 use handlers::activity_pub::{inbox, outbox};
+use handlers::jwks::jwks;
 use handlers::webfinger::webfinger;
-use middleware::jwt_middleware::jwt_middleware;
-use sqlx::PgPool;
+use metrics::Metrics;
+use middleware::metrics::RecordMetrics;
+use middleware::request_id::TraceRequests;
+use ruleset::Ruleset;
+use sqlx::ConnectOptions;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use std::str::FromStr;
+use std::time::Duration;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init(); // optional: logs
+    let config = Config::load();
+    // Log with the configured format, or the default one to report a broken config
+    telemetry::init(
+        &config
+            .as_ref()
+            .map(|c| c.logging.clone())
+            .unwrap_or_default(),
+    );
+    let config = config.unwrap_or_else(|e| {
+        tracing::error!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    });
 
-    let config = Config::from_env();
-    let pool = PgPool::connect(&config.database_url)
+    // Every statement is logged at debug level (target `sqlx::query`), slow ones as warnings.
+    // Query helpers wrap theirs in debug spans (target `db`) to time and group them.
+    let connect_options = PgConnectOptions::from_str(&config.database.url)
+        .expect("Invalid database URL")
+        .log_slow_statements(
+            log::LevelFilter::Warn,
+            Duration::from_millis(config.database.slow_statement_ms),
+        );
+    let pool = PgPoolOptions::new()
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .acquire_timeout(Duration::from_secs(config.database.acquire_timeout_seconds))
+        .connect_with(connect_options)
         .await
         .expect("Failed to connect to Postgres");
-    let jwt_secret = config.jwt_secret.clone();
+    let features = config.features;
+    let jwt_keys = web::Data::new(JwtKeys::from_config(&config).unwrap_or_else(|e| {
+        tracing::error!("Cannot load JWT keys: {}", e);
+        std::process::exit(1);
+    }));
+    let hasher = web::Data::new(Hasher::from_config(&config).unwrap_or_else(|e| {
+        tracing::error!("Invalid password hashing settings: {}", e);
+        std::process::exit(1);
+    }));
+    let mailer = web::Data::from(mail::from_config(&config).unwrap_or_else(|e| {
+        tracing::error!("Cannot set up mailer: {}", e);
+        std::process::exit(1);
+    }));
+    let ruleset = web::Data::new(Ruleset::from_config(&config).unwrap_or_else(|e| {
+        tracing::error!("Invalid battle ruleset: {}", e);
+        std::process::exit(1);
+    }));
+    if let Err(e) = ruleset.register(&pool).await {
+        tracing::error!("Cannot register battle ruleset: {}", e);
+        std::process::exit(1);
+    }
+    tracing::info!(
+        ruleset = %ruleset.name,
+        version = ruleset.version,
+        "Loaded battle ruleset"
+    );
+    let metrics = Metrics::new().expect("Failed to register metrics");
+
+    // Hard-delete accounts whose deletion grace period has run out, and drop stale
+    // login throttles and audit rows
+    let purge_pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            match handlers::account::purge_deleted_accounts(&purge_pool).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "Deleted accounts after their grace period"),
+                Err(e) => tracing::error!(error = %e, "Failed to purge deleted accounts"),
+            }
+            match auth::throttle::purge_expired(&purge_pool).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "Deleted expired login throttles and failures"),
+                Err(e) => tracing::error!(error = %e, "Failed to purge login throttles"),
+            }
+        }
+    });
+
     HttpServer::new(move || {
         App::new()
             // store the pool in App data so routes can access
             .app_data(web::Data::new(pool.clone()))
-            // also store the JWT secret if you want via app data
-            .app_data(web::Data::new(jwt_secret.clone()))
+            // keys used to sign and verify access tokens
+            .app_data(jwt_keys.clone())
+            // Argon2 settings for password hashes
+            .app_data(hasher.clone())
+            // outgoing email (password resets, ...)
+            .app_data(mailer.clone())
+            .app_data(web::Data::new(config.server.public_base_url.clone()))
+            .app_data(web::Data::new(config.server.trusted_proxies.clone()))
+            .app_data(web::Data::new(config.tokens))
+            .app_data(web::Data::new(config.email_verification))
+            .app_data(web::Data::new(config.account_deletion))
+            .app_data(web::Data::new(config.simulator.clone()))
+            // unit stats for battles
+            .app_data(ruleset.clone())
+            .app_data(web::Data::new(features))
+            .app_data(web::Data::new(metrics.clone()))
+            // malformed bodies, paths and query strings answer with problem+json
+            .app_data(web::JsonConfig::default().error_handler(error::bad_request_handler))
+            .app_data(web::PathConfig::default().error_handler(error::bad_request_handler))
+            .app_data(web::QueryConfig::default().error_handler(error::bad_request_handler))
+            // probes for the orchestrator
+            .configure(handlers::health::config)
+            .configure(handlers::metrics::config)
             // user routes (register, login, me) from user_handlers
             .configure(handlers::user::config)
+            .configure(handlers::account::config)
+            .configure(|cfg| {
+                if features.guest_accounts {
+                    handlers::guest::config(cfg);
+                }
+            })
+            .configure(handlers::password::config)
+            .configure(handlers::verification::config)
+            .configure(handlers::two_factor::config)
+            .configure(|cfg| {
+                if features.api_keys {
+                    handlers::api_keys::config(cfg);
+                }
+            })
             .configure(handlers::simulator::config)
+            .configure(handlers::battles::config)
             .configure(handlers::fleet::config)
+            .configure(handlers::admin::config)
             // SSE + WebSockets
-            .route("/sse", web::get().to(handlers::sse::sse_endpoint))
-            .route("/ws/", web::get().to(handlers::websocket::ws_index))
+            .configure(|cfg| {
+                if features.sse {
+                    cfg.route("/sse", web::get().to(handlers::sse::sse_endpoint));
+                }
+                if features.websocket {
+                    cfg.route("/ws/", web::get().to(handlers::websocket::ws_index));
+                }
+            })
             .service(
                 web::scope("/actor")
-                    .wrap(HttpAuthentication::bearer(jwt_middleware))
                     .route(
                         "/{username}",
                         web::get().to(handlers::activity_pub::get_actor),
@@ -45,13 +172,17 @@ async fn main() -> std::io::Result<()> {
                     .route("/{username}/outbox", web::get().to(outbox)),
             )
             .route("/.well-known/webfinger", web::get().to(webfinger))
+            .route("/.well-known/jwks.json", web::get().to(jwks))
             .route(
                 "/battle-request/",
                 web::post().to(handlers::simulator::send_battle_request_handler),
             )
+            // request counts and latency for every route above
+            .wrap(RecordMetrics(metrics.clone()))
+            // request ID and tracing span; outermost so the other middleware log inside it
+            .wrap(TraceRequests)
     })
-    // .bind(("127.0.0.1", 8080))?
-    .bind(("0.0.0.0", 8080))?
+    .bind(config.server.bind_addr)?
     .run()
     .await
 }
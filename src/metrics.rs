@@ -0,0 +1,165 @@
+// src/metrics.rs
+// Prometheus metrics, served in text format on GET /metrics. One `Metrics` is
+// built at startup and shared through `web::Data`; the metric handles are cheap
+// clones of the same counters.
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder, exponential_buckets,
+};
+use sqlx::PgPool;
+
+/// Inbox activity types with their own label value; anything else counts as "other"
+/// so clients cannot create unbounded label values.
+const KNOWN_ACTIVITY_TYPES: &[&str] = &["BattleRequest", "Message"];
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    pub websocket_connections: IntGauge,
+    pub sse_connections: IntGauge,
+    battles: IntCounter,
+    battle_rounds: Histogram,
+    logins: IntCounterVec,
+    inbox_activities: IntCounterVec,
+    db_connections: IntGaugeVec,
+    db_max_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by route, method and status",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route and method",
+            ),
+            &["method", "route"],
+        )?;
+        let websocket_connections =
+            IntGauge::new("websocket_connections", "Open WebSocket connections")?;
+        let sse_connections = IntGauge::new("sse_connections", "Open SSE streams")?;
+        let battles = IntCounter::new("battles_simulated_total", "Battles simulated")?;
+        let battle_rounds = Histogram::with_opts(
+            HistogramOpts::new("battle_rounds", "Rounds fought per simulated battle")
+                .buckets(exponential_buckets(1.0, 2.0, 11)?),
+        )?;
+        let logins = IntCounterVec::new(
+            Opts::new(
+                "logins_total",
+                "Login attempts by result (success or failure)",
+            ),
+            &["result"],
+        )?;
+        let inbox_activities = IntCounterVec::new(
+            Opts::new(
+                "inbox_activities_total",
+                "ActivityPub activities delivered to inboxes by type",
+            ),
+            &["type"],
+        )?;
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Postgres pool connections by state"),
+            &["state"],
+        )?;
+        let db_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Upper limit of the Postgres pool",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(websocket_connections.clone()))?;
+        registry.register(Box::new(sse_connections.clone()))?;
+        registry.register(Box::new(battles.clone()))?;
+        registry.register(Box::new(battle_rounds.clone()))?;
+        registry.register(Box::new(logins.clone()))?;
+        registry.register(Box::new(inbox_activities.clone()))?;
+        registry.register(Box::new(db_connections.clone()))?;
+        registry.register(Box::new(db_max_connections.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            websocket_connections,
+            sse_connections,
+            battles,
+            battle_rounds,
+            logins,
+            inbox_activities,
+            db_connections,
+            db_max_connections,
+        })
+    }
+
+    /// `route` is the matched pattern (e.g. `/actor/{username}`), never the raw path.
+    pub fn record_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(seconds);
+    }
+
+    pub fn record_battle(&self, rounds: u32) {
+        self.battles.inc();
+        self.battle_rounds.observe(rounds as f64);
+    }
+
+    pub fn record_login(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.logins.with_label_values(&[result]).inc();
+    }
+
+    pub fn record_inbox_activity(&self, activity_type: &str) {
+        let label = KNOWN_ACTIVITY_TYPES
+            .iter()
+            .find(|known| **known == activity_type)
+            .copied()
+            .unwrap_or("other");
+        self.inbox_activities.with_label_values(&[label]).inc();
+    }
+
+    /// Everything in the Prometheus text format. Pool gauges are sampled now.
+    pub fn render(&self, pool: &PgPool) -> prometheus::Result<String> {
+        let size = pool.size() as i64;
+        let idle = pool.num_idle() as i64;
+        self.db_connections.with_label_values(&["idle"]).set(idle);
+        self.db_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+        self.db_max_connections
+            .set(pool.options().get_max_connections() as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Counts one open connection on a gauge for as long as it is alive.
+pub struct ConnectionGuard(IntGauge);
+
+impl ConnectionGuard {
+    pub fn new(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        ConnectionGuard(gauge.clone())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
@@ -0,0 +1,141 @@
+use crate::metrics::Metrics;
+use actix_web::Error;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use futures_util::future::LocalBoxFuture;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Counts every request and its latency, labelled by method, matched route and status,
+/// including requests that fail with an error before a response is built.
+///
+/// Wrap the whole app, e.g. `.wrap(RecordMetrics(metrics.clone()))`. Requests that match
+/// no route are labelled `unmatched` so scanners cannot blow up the label set.
+pub struct RecordMetrics(pub Metrics);
+
+impl<S, B> Transform<S, ServiceRequest> for RecordMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RecordMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordMetricsMiddleware {
+            service: Rc::new(service),
+            metrics: self.0.clone(),
+        }))
+    }
+}
+
+pub struct RecordMetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+
+        // Taken up front: a failed request has no response to read them from. The
+        // pattern comes from the app's resource map, so it is known before routing.
+        let method = req.method().clone();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+
+            // Errors from inner middleware are counted under the status they render as
+            let status = match &result {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            metrics.record_request(
+                method.as_str(),
+                &route,
+                status.as_u16(),
+                started.elapsed().as_secs_f64(),
+            );
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpResponse, error, test, web};
+    use sqlx::postgres::PgPoolOptions;
+
+    /// `http_requests_total` lines of the rendered metrics.
+    fn request_counts(metrics: &Metrics) -> Vec<String> {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        metrics
+            .render(&pool)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("http_requests_total{"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn counts_requests_that_fail_in_inner_middleware() {
+        let metrics = Metrics::new().unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let fail = req.path().starts_with("/fleet/");
+                    let res = srv.call(req);
+                    async move {
+                        if fail {
+                            return Err(error::ErrorInternalServerError("boom"));
+                        }
+                        res.await
+                    }
+                })
+                .wrap(RecordMetrics(metrics.clone()))
+                .route("/fleet/{id}", web::get().to(HttpResponse::Ok))
+                .route("/health", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for uri in ["/fleet/1", "/health", "/nowhere"] {
+            let _ = app
+                .call(test::TestRequest::get().uri(uri).to_request())
+                .await;
+        }
+
+        assert_eq!(
+            request_counts(&metrics),
+            [
+                r#"http_requests_total{method="GET",route="/fleet/{id}",status="500"} 1"#,
+                r#"http_requests_total{method="GET",route="/health",status="200"} 1"#,
+                r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+            ]
+        );
+    }
+}
@@ -1 +1,3 @@
-pub mod jwt_middleware;
+pub mod metrics;
+pub mod request_id;
+pub mod require_role;
@@ -0,0 +1,172 @@
+use crate::auth::api_key::Scope;
+use crate::auth::extractor::authenticate;
+use crate::auth::roles::Role;
+use crate::error::AppError;
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::{Error, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+
+/// Rejects requests whose bearer token does not carry the given role.
+///
+/// Usable on any route or scope, e.g. `.wrap(RequireRole(Role::Admin))`.
+/// Responds 401 without a valid token and 403 when the role is missing.
+/// API keys additionally need the `admin` scope to pass `RequireRole(Role::Admin)`.
+/// The handler can still take an `AuthenticatedUser` without decoding the token twice.
+pub struct RequireRole(pub Role);
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            role: self.0,
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<S>,
+    role: Role,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let role = self.role;
+
+        Box::pin(async move {
+            // Answer here rather than returning `Err`, so outer middleware (request IDs,
+            // metrics) see the 401/403 like any other response
+            if let Err(e) = authorize(req.request(), role).await {
+                return Ok(req.error_response(e).map_into_right_body());
+            }
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+async fn authorize(req: &HttpRequest, role: Role) -> Result<(), AppError> {
+    let user = authenticate(req).await?;
+
+    if !user.has_role(role) {
+        return Err(AppError::forbidden(format!("Requires role: {}", role)));
+    }
+
+    if role == Role::Admin {
+        user.require_scope(Scope::Admin)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::extractor::AuthenticatedUser;
+    use crate::auth::jwt::{JwtKeys, generate_jwt};
+    use actix_web::http::StatusCode;
+    use actix_web::{App, HttpMessage, HttpResponse, test, web};
+    use uuid::Uuid;
+
+    fn keys() -> web::Data<JwtKeys> {
+        web::Data::new(JwtKeys::from_secret("test-secret"))
+    }
+
+    async fn call(auth: Option<String>) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .app_data(keys())
+                .wrap(RequireRole(Role::Admin))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let mut req = test::TestRequest::get().uri("/");
+        if let Some(auth) = auth {
+            req = req.insert_header(("Authorization", auth));
+        }
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    fn bearer(roles: &[Role]) -> Option<String> {
+        let token = generate_jwt(&Uuid::new_v4().to_string(), roles, &keys());
+        Some(format!("Bearer {}", token))
+    }
+
+    #[actix_web::test]
+    async fn admin_token_passes() {
+        assert_eq!(
+            call(bearer(&[Role::Player, Role::Admin])).await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn non_admin_is_forbidden() {
+        assert_eq!(call(bearer(&[Role::Player])).await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn missing_token_is_unauthorized() {
+        assert_eq!(call(None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    /// API keys are looked up in the database, so stand in for the lookup by
+    /// attaching the key's owner to the request the way `authenticate` does.
+    async fn call_with_api_key(scopes: Vec<Scope>) -> StatusCode {
+        let user = AuthenticatedUser {
+            id: Uuid::new_v4(),
+            roles: vec![Role::Player, Role::Admin],
+            scopes: Some(scopes),
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(RequireRole(Role::Admin))
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(user.clone());
+                    srv.call(req)
+                })
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        test::call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn admin_api_key_needs_admin_scope() {
+        assert_eq!(
+            call_with_api_key(vec![Scope::Profile, Scope::Fleets]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(call_with_api_key(vec![Scope::Admin]).await, StatusCode::OK);
+    }
+}
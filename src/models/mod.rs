@@ -1,3 +1,3 @@
 pub mod activity_pub;
+pub mod api_key;
 pub mod user;
-
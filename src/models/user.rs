@@ -9,8 +9,19 @@ use uuid::Uuid;
 pub struct User {
     pub id: Uuid,
     pub username: String,
-    pub email: String,
+    pub email: Option<String>, // `None` for guests
     #[serde(skip_serializing)]
-    pub password: String,
+    pub password: Option<String>, // `None` for guests
+    pub roles: Vec<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>, // set while a deletion request is pending
     pub created_at: DateTime<Utc>,
 }
+
+impl User {
+    /// Guests play without email and password until they upgrade.
+    pub fn is_guest(&self) -> bool {
+        self.email.is_none()
+    }
+}
@@ -0,0 +1,273 @@
+// src/ruleset.rs
+// Unit stats for the battle simulator, read from a TOML file at startup (see
+// `rulesets/default.toml`, which is also built in). Shared as app data, and stored in
+// `rulesets` by name and version so stored battles can be replayed with their stats.
+use crate::config::{Config, ConfigError};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const BUILTIN_RULESET: &str = include_str!("../rulesets/default.toml");
+/// Upper bounds for stats, rolls and bonuses; they keep the simulator's math from overflowing.
+const MAX_STAT: i64 = 1_000_000_000;
+const MAX_ROLL_PERCENT: i64 = 10_000;
+const MAX_RAPID_FIRE: i64 = 1_000;
+
+/// The unit types a fleet is made of, one column each in `fleets`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitType {
+    Ships,
+    Fighters,
+    Bombers,
+}
+
+impl UnitType {
+    pub const ALL: [UnitType; 3] = [UnitType::Ships, UnitType::Fighters, UnitType::Bombers];
+
+    /// Position in `ALL`, for per-type arrays.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for UnitType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnitType::Ships => "ships",
+            UnitType::Fighters => "fighters",
+            UnitType::Bombers => "bombers",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnitStats {
+    pub attack: i64,  // damage per unit and round, before the roll
+    pub hull: i64,    // damage a unit takes before it is destroyed
+    pub shields: i64, // damage soaked per unit and round
+    /// Damage multiplier against the given types; 1 for the rest.
+    #[serde(default)]
+    pub rapid_fire: BTreeMap<UnitType, i64>,
+}
+
+impl UnitStats {
+    pub fn rapid_fire_against(&self, target: UnitType) -> i64 {
+        self.rapid_fire.get(&target).copied().unwrap_or(1)
+    }
+}
+
+/// Range of the per-round, per-unit-type fire roll, in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Roll {
+    pub min_percent: i64,
+    pub max_percent: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ruleset {
+    pub name: String,
+    pub version: u32,
+    pub roll: Roll,
+    units: BTreeMap<UnitType, UnitStats>,
+    /// From `simulator.max_rounds`; the battle is decided on remaining units after this many rounds.
+    #[serde(skip)]
+    pub max_rounds: u32,
+}
+
+impl Ruleset {
+    /// The file in `simulator.ruleset`, or the built-in ruleset.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let (source, text) = match &config.simulator.ruleset {
+            Some(path) => (
+                path.clone(),
+                std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?,
+            ),
+            None => ("built-in ruleset".to_string(), BUILTIN_RULESET.to_string()),
+        };
+
+        let mut ruleset: Ruleset =
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(source.clone(), e))?;
+        ruleset.max_rounds = config.simulator.max_rounds;
+        ruleset
+            .validate()
+            .map_err(|problem| ConfigError::Invalid(source, problem))?;
+        Ok(ruleset)
+    }
+
+    /// Store this ruleset under its name and version. Fails if they were stored before
+    /// with other stats: a balance change needs a new version, or old battles would
+    /// replay with the wrong stats.
+    #[tracing::instrument(target = "db", level = "debug", skip_all, fields(name = %self.name, version = self.version))]
+    pub async fn register(&self, pool: &PgPool) -> Result<(), String> {
+        let rules = serde_json::to_value(self).map_err(|e| e.to_string())?;
+
+        sqlx::query!(
+            "INSERT INTO rulesets (name, version, rules) VALUES ($1, $2, $3)
+             ON CONFLICT (name, version) DO NOTHING",
+            self.name,
+            self.version as i32,
+            rules
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let stored = Ruleset::stored(pool, &self.name, self.version as i32, self.max_rounds)
+            .await
+            .map_err(|e| e.to_string())?;
+        match stored {
+            Some(stored) if stored.same_stats(self) => Ok(()),
+            _ => Err(format!(
+                "{} version {} was stored with other stats; give the changed ruleset a new version",
+                self.name, self.version
+            )),
+        }
+    }
+
+    /// A ruleset from `rulesets`, with `max_rounds` as the battle was fought with.
+    #[tracing::instrument(target = "db", level = "debug", skip_all, fields(name, version))]
+    pub async fn stored(
+        pool: &PgPool,
+        name: &str,
+        version: i32,
+        max_rounds: u32,
+    ) -> Result<Option<Ruleset>, sqlx::Error> {
+        let rules = sqlx::query_scalar!(
+            "SELECT rules FROM rulesets WHERE name = $1 AND version = $2",
+            name,
+            version
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(rules) = rules else {
+            return Ok(None);
+        };
+        let mut ruleset: Ruleset =
+            serde_json::from_value(rules).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+        ruleset.max_rounds = max_rounds;
+        Ok(Some(ruleset))
+    }
+
+    /// Whether battles fought under `other` replay the same under `self`.
+    fn same_stats(&self, other: &Ruleset) -> bool {
+        self.roll == other.roll && self.units == other.units
+    }
+
+    pub fn unit(&self, unit: UnitType) -> &UnitStats {
+        // Every type is present once `validate` passed
+        &self.units[&unit]
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.version == 0 {
+            return Err("version: must be at least 1".to_string());
+        }
+        if self.max_rounds == 0 {
+            return Err("max_rounds: must be at least 1".to_string());
+        }
+
+        if self.roll.min_percent < 0
+            || self.roll.max_percent < self.roll.min_percent
+            || self.roll.max_percent > MAX_ROLL_PERCENT
+        {
+            return Err(format!(
+                "roll: need 0 <= min_percent <= max_percent <= {}",
+                MAX_ROLL_PERCENT
+            ));
+        }
+
+        for unit in UnitType::ALL {
+            let stats = self
+                .units
+                .get(&unit)
+                .ok_or_else(|| format!("units.{}: missing", unit))?;
+            if !(0..=MAX_STAT).contains(&stats.attack) || !(0..=MAX_STAT).contains(&stats.shields) {
+                return Err(format!(
+                    "units.{}: attack and shields must be between 0 and {}",
+                    unit, MAX_STAT
+                ));
+            }
+            if !(1..=MAX_STAT).contains(&stats.hull) {
+                return Err(format!(
+                    "units.{}: hull must be between 1 and {}",
+                    unit, MAX_STAT
+                ));
+            }
+            if let Some((target, _)) = stats
+                .rapid_fire
+                .iter()
+                .find(|(_, factor)| !(1..=MAX_RAPID_FIRE).contains(*factor))
+            {
+                return Err(format!(
+                    "units.{}.rapid_fire.{}: must be between 1 and {}",
+                    unit, target, MAX_RAPID_FIRE
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin() -> Ruleset {
+        Ruleset::from_config(&Config::default()).expect("built-in ruleset is valid")
+    }
+
+    fn problem(ruleset: &Ruleset) -> String {
+        ruleset.validate().expect_err("ruleset should be invalid")
+    }
+
+    #[test]
+    fn builtin_ruleset_has_every_unit_type() {
+        let ruleset = builtin();
+        for unit in UnitType::ALL {
+            assert!(ruleset.unit(unit).hull > 0, "{unit}");
+        }
+    }
+
+    #[test]
+    fn zero_hull_is_rejected() {
+        let mut ruleset = builtin();
+        ruleset.units.get_mut(&UnitType::Fighters).unwrap().hull = 0;
+        assert!(problem(&ruleset).starts_with("units.fighters: hull"));
+    }
+
+    #[test]
+    fn missing_unit_type_is_rejected() {
+        let mut ruleset = builtin();
+        ruleset.units.remove(&UnitType::Bombers);
+        assert_eq!(problem(&ruleset), "units.bombers: missing");
+    }
+
+    #[test]
+    fn zero_max_rounds_is_rejected() {
+        let mut ruleset = builtin();
+        ruleset.max_rounds = 0;
+        assert!(problem(&ruleset).starts_with("max_rounds"));
+    }
+
+    #[test]
+    fn changed_stats_need_a_new_version() {
+        let stored = builtin();
+        let mut changed = builtin();
+        changed.max_rounds = stored.max_rounds + 1; // not part of the stored rules
+        assert!(stored.same_stats(&changed));
+
+        changed.units.get_mut(&UnitType::Ships).unwrap().attack += 1;
+        assert!(!stored.same_stats(&changed));
+
+        let mut changed = builtin();
+        changed.roll.max_percent -= 1;
+        assert!(!stored.same_stats(&changed));
+    }
+}
@@ -0,0 +1,22 @@
+// src/telemetry.rs
+// Structured logging with `tracing`. Every request runs in a `request` span (see
+// `middleware::request_id`), so events from handlers, sqlx and battles carry its
+// `request_id` and `user_id`. Records from crates that use `log` are forwarded too.
+use crate::config::{LogFormat, Logging};
+use tracing_subscriber::EnvFilter;
+
+/// Install the global subscriber. Call once, before anything logs.
+pub fn init(logging: &Logging) {
+    let filter = EnvFilter::try_new(&logging.filter).unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match logging.format {
+        LogFormat::Text => subscriber.init(),
+        // `span` holds the innermost span, `spans` the whole chain up to `request`
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
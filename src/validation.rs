@@ -0,0 +1,327 @@
+// src/validation.rs
+// Checks for user-supplied account data. Handlers collect every problem into a
+// `ValidationErrors` so clients can show them next to the right form fields.
+use std::collections::BTreeMap;
+use std::fmt;
+
+const USERNAME_MIN_LEN: usize = 3;
+const USERNAME_MAX_LEN: usize = 32;
+const PASSWORD_MIN_LEN: usize = 8;
+const PASSWORD_MAX_LEN: usize = 128;
+const EMAIL_MAX_LEN: usize = 254;
+
+/// Names that could be mistaken for staff or clash with routes. Compared case-insensitively.
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "root",
+    "system",
+    "support",
+    "moderator",
+    "staff",
+    "official",
+    "guest",
+    "api",
+    "www",
+    "localhost",
+    "actor",
+    "inbox",
+    "outbox",
+    "webfinger",
+    "me",
+];
+
+/// Passwords too common to be worth guessing protection.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password",
+    "password1",
+    "password123",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "qwertyuiop",
+    "qwerty123",
+    "iloveyou",
+    "11111111",
+    "00000000",
+    "abc12345",
+    "letmein1",
+    "welcome1",
+    "sunshine",
+    "football",
+    "baseball",
+    "starwars",
+];
+
+/// Every failed check, grouped by field. Rendered by `AppError::Validation` as a 422
+/// with `"errors": {"email": ["..."]}`.
+#[derive(Debug, Default)]
+pub struct ValidationErrors {
+    fields: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.fields.entry(field).or_default().push(message.into());
+    }
+
+    pub fn fields(&self) -> &BTreeMap<&'static str, Vec<String>> {
+        &self.fields
+    }
+
+    /// `Ok` if no check failed.
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.fields.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<&str> = self.fields.keys().copied().collect();
+        write!(f, "Invalid input: {}", fields.join(", "))
+    }
+}
+
+/// The local part of a username; `@<domain>` of the public URL is appended on registration.
+pub fn check_username(errors: &mut ValidationErrors, username: &str) {
+    let len = username.chars().count();
+    if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&len) {
+        errors.add(
+            "username",
+            format!(
+                "Must be between {} and {} characters",
+                USERNAME_MIN_LEN, USERNAME_MAX_LEN
+            ),
+        );
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        errors.add(
+            "username",
+            "May only contain letters, digits, '_', '-' and '.'",
+        );
+    } else if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        errors.add("username", "Must start with a letter or digit");
+    }
+
+    let lower = username.to_ascii_lowercase();
+    if RESERVED_USERNAMES.contains(&lower.as_str()) || lower.starts_with("guest-") {
+        errors.add("username", "This name is reserved");
+    }
+}
+
+/// A plausible address: one `@`, a non-empty local part and a dotted domain.
+/// Whether it exists is up to email verification.
+pub fn check_email(errors: &mut ValidationErrors, email: &str) {
+    let valid = email.len() <= EMAIL_MAX_LEN
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && local.len() <= 64
+                && !domain.contains('@')
+                && domain.contains('.')
+                && domain.split('.').all(|label| {
+                    !label.is_empty()
+                        && !label.starts_with('-')
+                        && !label.ends_with('-')
+                        && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+                })
+        });
+
+    if !valid {
+        errors.add("email", "Not a valid email address");
+    }
+}
+
+/// Length limits plus rejection of common passwords and passwords built from
+/// the other account details (`related`, e.g. username and email).
+pub fn check_password(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    password: &str,
+    related: &[&str],
+) {
+    let len = password.chars().count();
+    if len < PASSWORD_MIN_LEN {
+        errors.add(
+            field,
+            format!("Must be at least {} characters", PASSWORD_MIN_LEN),
+        );
+    } else if len > PASSWORD_MAX_LEN {
+        errors.add(
+            field,
+            format!("Must be at most {} characters", PASSWORD_MAX_LEN),
+        );
+    }
+
+    let lower = password.to_lowercase();
+    if COMMON_PASSWORDS.contains(&lower.as_str()) {
+        errors.add(field, "Too common");
+    }
+
+    if related
+        .iter()
+        .map(|value| value.to_lowercase())
+        .any(|value| value.len() >= USERNAME_MIN_LEN && lower.contains(&value))
+    {
+        errors.add(field, "Must not contain your username or email");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Messages reported for `field` by one check.
+    fn errors_for(field: &str, check: impl FnOnce(&mut ValidationErrors)) -> Vec<String> {
+        let mut errors = ValidationErrors::default();
+        check(&mut errors);
+        errors.fields().get(field).cloned().unwrap_or_default()
+    }
+
+    fn username_errors(username: &str) -> Vec<String> {
+        errors_for("username", |errors| check_username(errors, username))
+    }
+
+    fn email_errors(email: &str) -> Vec<String> {
+        errors_for("email", |errors| check_email(errors, email))
+    }
+
+    fn password_errors(password: &str, related: &[&str]) -> Vec<String> {
+        errors_for("password", |errors| {
+            check_password(errors, "password", password, related)
+        })
+    }
+
+    #[test]
+    fn accepts_ordinary_usernames() {
+        for username in [
+            "bob",
+            "Star_Pilot-7",
+            "a.b.c",
+            &"x".repeat(USERNAME_MAX_LEN),
+        ] {
+            assert!(username_errors(username).is_empty(), "{username}");
+        }
+    }
+
+    #[test]
+    fn username_length_limits() {
+        assert_eq!(username_errors("ab").len(), 1);
+        assert_eq!(username_errors(&"x".repeat(USERNAME_MAX_LEN + 1)).len(), 1);
+        assert!(!username_errors("").is_empty());
+    }
+
+    #[test]
+    fn username_characters() {
+        assert!(!username_errors("bob smith").is_empty());
+        assert!(!username_errors("bob@example.com").is_empty());
+        assert!(!username_errors("björn").is_empty());
+        assert_eq!(
+            username_errors("_bob"),
+            ["Must start with a letter or digit"]
+        );
+    }
+
+    #[test]
+    fn reserved_usernames_in_any_case() {
+        for username in ["admin", "Admin", "ROOT", "webfinger", "guest"] {
+            assert_eq!(
+                username_errors(username),
+                ["This name is reserved"],
+                "{username}"
+            );
+        }
+    }
+
+    #[test]
+    fn guest_prefix_is_reserved() {
+        assert_eq!(username_errors("guest-1234"), ["This name is reserved"]);
+        assert_eq!(username_errors("Guest-abc"), ["This name is reserved"]);
+        assert!(username_errors("guestbook").is_empty());
+    }
+
+    #[test]
+    fn accepts_ordinary_emails() {
+        for email in ["bob@example.com", "a.b+tag@mail.example.co.uk", "x@b.io"] {
+            assert!(email_errors(email).is_empty(), "{email}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_emails() {
+        for email in [
+            "a@b",
+            "",
+            "bob",
+            "@example.com",
+            "bob@",
+            "bob@@example.com",
+            "bob@exa@mple.com",
+            "bob@example..com",
+            "bob@-example.com",
+            "bob@example.com.",
+            "bob smith@example.com",
+            "bob@exam_ple.com",
+        ] {
+            assert_eq!(
+                email_errors(email),
+                ["Not a valid email address"],
+                "{email}"
+            );
+        }
+    }
+
+    #[test]
+    fn email_length_limits() {
+        let local = "x".repeat(64);
+        assert!(email_errors(&format!("{local}@example.com")).is_empty());
+        assert!(!email_errors(&format!("{local}x@example.com")).is_empty());
+
+        let domain = format!("{}.com", "d".repeat(EMAIL_MAX_LEN - "bob@.com".len()));
+        assert!(email_errors(&format!("bob@{domain}")).is_empty());
+        assert!(!email_errors(&format!("bob@d{domain}")).is_empty());
+    }
+
+    #[test]
+    fn password_length_limits() {
+        let short = "x".repeat(PASSWORD_MIN_LEN - 1);
+        assert_eq!(
+            password_errors(&short, &[]),
+            [format!("Must be at least {} characters", PASSWORD_MIN_LEN)]
+        );
+        assert!(password_errors(&"x".repeat(PASSWORD_MIN_LEN), &[]).is_empty());
+        assert!(password_errors(&"x".repeat(PASSWORD_MAX_LEN), &[]).is_empty());
+        assert_eq!(
+            password_errors(&"x".repeat(PASSWORD_MAX_LEN + 1), &[]),
+            [format!("Must be at most {} characters", PASSWORD_MAX_LEN)]
+        );
+    }
+
+    #[test]
+    fn rejects_common_passwords_in_any_case() {
+        for password in ["password", "Password123", "QWERTYUIOP", "12345678"] {
+            assert_eq!(password_errors(password, &[]), ["Too common"], "{password}");
+        }
+        assert!(password_errors("correct-horse-battery", &[]).is_empty());
+    }
+
+    #[test]
+    fn rejects_passwords_containing_username_or_email() {
+        let related = ["StarPilot", "bob@example.com"];
+        let expected = ["Must not contain your username or email"];
+
+        assert_eq!(password_errors("my-starpilot-pw", &related), expected);
+        assert_eq!(password_errors("xxBOB@EXAMPLE.COMxx", &related), expected);
+        assert!(password_errors("correct-horse-battery", &related).is_empty());
+        // Values shorter than a username can't be checked without false positives
+        assert!(password_errors("correct-horse-battery", &["co"]).is_empty());
+    }
+}